@@ -119,8 +119,48 @@ pub fn parse(input_str: &str) -> Result<FmtSegmentVec, FormatStrError> {
         }
     }
 
+    // A variable that is never closed, or a backslash with nothing after it to escape
+    if current_state != ParserState::Literal || is_escaped {
+        return Err(FormatStrError::AbruptEnd(
+            current_state,
+            input_str.chars().count(),
+        ));
+    }
+
+    // The literal after the last variable
+    if !current_literal.is_empty() {
+        segments.push(Segment::Literal(current_literal));
+    }
+
     Ok(FmtSegmentVec {
         min_length,
         inner: segments,
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_trailing_literal() {
+        let parsed = parse("{percentage}%").unwrap();
+        assert_eq!(parsed.inner.last(), Some(&Segment::Literal("%".to_owned())));
+    }
+
+    #[test]
+    fn rejects_abrupt_end() {
+        assert!(matches!(
+            parse("{percentage"),
+            Err(FormatStrError::AbruptEnd(ParserState::VarIdent, _))
+        ));
+        assert!(matches!(
+            parse("100\\"),
+            Err(FormatStrError::AbruptEnd(ParserState::Literal, 4))
+        ));
+        assert_eq!(
+            parse("100\\\\").unwrap().inner,
+            [Segment::Literal("100\\".to_owned())]
+        );
+    }
+}
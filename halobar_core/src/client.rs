@@ -0,0 +1,237 @@
+use crate::imports::*;
+
+/// The different states for a request
+#[derive(Debug, Clone, PartialEq)]
+pub enum Request {
+    Request(RequestField),
+    Fulfilled(ModuleData),
+    Error(ProviderError),
+}
+impl Request {
+    pub fn resolve(&mut self, data: ModuleData) {
+        *self = Self::Fulfilled(data)
+    }
+    pub fn reject(&mut self, error: ProviderError) {
+        *self = Self::Error(error)
+    }
+    /// Return this as an error with [`ProviderError::InvalidRequest`].
+    ///
+    /// Clones and boxes the request internally.
+    pub fn reject_invalid(&mut self) {
+        let boxed_req = Box::new(self.clone());
+        *self = Request::Error(ProviderError::InvalidRequest(boxed_req))
+    }
+}
+
+/// An error that a provider hits while getting data for a widget.
+///
+/// This is sent to the widget, which shows it with its error format until it receives new data.
+#[derive(Debug, Clone, PartialEq, thiserror::Error, strum_macros::IntoStaticStr)]
+#[strum(serialize_all = "snake_case")]
+pub enum ProviderError {
+    #[error("The field you provided is not a field this module supports: {0:?}")]
+    InvalidField(RequestField),
+    /// The service that provides the data is not running or can't be reached
+    #[error("{service} is unavailable")]
+    Unavailable { service: String, source: ErrorChain },
+    #[error("Permission denied")]
+    PermissionDenied { source: ErrorChain },
+    #[error("Timed out")]
+    Timeout { source: ErrorChain },
+    /// The data was received, but it was not in the expected format
+    #[error("Failed to parse {what}")]
+    Parse { what: String, source: ErrorChain },
+    /// Any other error while getting the data
+    #[error("Error while getting the data")]
+    Query { source: ErrorChain },
+    /// The module that provides the data stopped
+    #[error("Module stopped")]
+    Stopped { source: ErrorChain },
+    #[error("Invalid data type")]
+    InvalidType,
+    #[error("Invalid request: {0:?}")]
+    InvalidRequest(Box<Request>),
+}
+impl ProviderError {
+    /// Get the kind of error, like `permission_denied`, for error formats
+    #[inline]
+    pub fn kind(&self) -> &'static str {
+        self.into()
+    }
+
+    /// Get the message of this error along with the messages of all of its sources, separated by colons.
+    pub fn report(&self) -> String {
+        let mut report = self.to_string();
+
+        let mut source = std::error::Error::source(self);
+        while let Some(error) = source {
+            report.push_str(": ");
+            report.push_str(&error.to_string());
+            source = error.source();
+        }
+
+        report
+    }
+
+    /// Rebuild an error from its kind and the messages that [`ErrorChain::new`] captured from it, like the errors in recordings.
+    ///
+    /// Errors that carry a field or a request can't be rebuilt, so they become [`ProviderError::Query`] errors with the same messages.
+    pub fn from_chain(kind: &str, chain: ErrorChain) -> Self {
+        let source = || match chain.source {
+            Some(ref source) => (**source).clone(),
+            None => ErrorChain::msg(chain.message.clone()),
+        };
+
+        match kind {
+            "unavailable" => {
+                if let Some(service) = chain.message.strip_suffix(" is unavailable") {
+                    return Self::Unavailable {
+                        service: service.to_owned(),
+                        source: source(),
+                    };
+                }
+            }
+            "permission_denied" => return Self::PermissionDenied { source: source() },
+            "timeout" => return Self::Timeout { source: source() },
+            "parse" => {
+                if let Some(what) = chain.message.strip_prefix("Failed to parse ") {
+                    return Self::Parse {
+                        what: what.to_owned(),
+                        source: source(),
+                    };
+                }
+            }
+            "query" => return Self::Query { source: source() },
+            "stopped" => return Self::Stopped { source: source() },
+            "invalid_type" => return Self::InvalidType,
+            _ => {}
+        }
+
+        Self::Query { source: chain }
+    }
+
+    /// Classify an error from a module that hit it while getting the data
+    pub fn query(error: &Report) -> Self {
+        #[cfg(feature = "zbus")]
+        if let Some(e) = error.downcast_ref::<zbus::Error>() {
+            return Self::from(e.clone());
+        }
+        if let Some(e) = error.downcast_ref::<io::Error>() {
+            return Self::from_io(e);
+        }
+
+        Self::Query {
+            source: ErrorChain::new(error.as_ref()),
+        }
+    }
+
+    /// Classify an io error
+    pub fn from_io(error: &io::Error) -> Self {
+        let source = ErrorChain::new(error);
+
+        match error.kind() {
+            io::ErrorKind::PermissionDenied => Self::PermissionDenied { source },
+            io::ErrorKind::TimedOut => Self::Timeout { source },
+            io::ErrorKind::NotFound
+            | io::ErrorKind::ConnectionRefused
+            | io::ErrorKind::ConnectionReset
+            | io::ErrorKind::BrokenPipe => Self::Unavailable {
+                service: "The resource".to_owned(),
+                source,
+            },
+            io::ErrorKind::InvalidData | io::ErrorKind::UnexpectedEof => Self::Parse {
+                what: "the output".to_owned(),
+                source,
+            },
+            _ => Self::Query { source },
+        }
+    }
+}
+#[cfg(feature = "zbus")]
+impl From<zbus::Error> for ProviderError {
+    fn from(error: zbus::Error) -> Self {
+        let source = ErrorChain::new(&error);
+
+        let name = match error {
+            zbus::Error::MethodError(ref name, ..) => name.to_string(),
+            zbus::Error::FDO(ref e) => zbus::DBusError::name(e.as_ref()).to_string(),
+            zbus::Error::InputOutput(ref e) => return Self::from_io(e),
+            zbus::Error::InterfaceNotFound => {
+                return Self::Unavailable {
+                    service: "The D-Bus interface".to_owned(),
+                    source,
+                }
+            }
+            zbus::Error::Variant(_) | zbus::Error::InvalidReply | zbus::Error::MissingField => {
+                return Self::Parse {
+                    what: "the D-Bus reply".to_owned(),
+                    source,
+                }
+            }
+            _ => return Self::Query { source },
+        };
+
+        // The names of the standard errors all start with org.freedesktop.DBus.Error
+        match name.rsplit('.').next().unwrap_or_default() {
+            "AccessDenied" | "AuthFailed" | "InteractiveAuthorizationRequired" => {
+                Self::PermissionDenied { source }
+            }
+            "ServiceUnknown" | "NameHasNoOwner" | "NoServer" | "Disconnected" | "UnknownObject" => {
+                Self::Unavailable {
+                    service: "The D-Bus service".to_owned(),
+                    source,
+                }
+            }
+            "Timeout" | "TimedOut" | "NoReply" => Self::Timeout { source },
+            _ => Self::Query { source },
+        }
+    }
+}
+
+/// An error that was turned into messages, so it can be cloned and sent to the frontend.
+///
+/// It keeps the sources of the original error.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ErrorChain {
+    message: String,
+    source: Option<Box<ErrorChain>>,
+}
+impl ErrorChain {
+    /// Capture the messages of this error and its sources
+    pub fn new(error: &(dyn std::error::Error + 'static)) -> Self {
+        Self {
+            message: error.to_string(),
+            source: error.source().map(|s| Box::new(Self::new(s))),
+        }
+    }
+
+    /// Create an error chain with a single message
+    pub fn msg(message: impl Into<String>) -> Self {
+        Self {
+            message: message.into(),
+            source: None,
+        }
+    }
+}
+impl std::fmt::Display for ErrorChain {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.message.fmt(f)
+    }
+}
+impl std::error::Error for ErrorChain {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        self.source
+            .as_deref()
+            .map(|s| s as &(dyn std::error::Error + 'static))
+    }
+}
+
+/// A request for some data from a backend data provider module.
+///
+/// Each module sends a single request to each backend info provider it needs.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DataRequest {
+    pub id: ModuleId,
+    pub data_fields: Vec<Request>,
+}
+impl DataRequest {}
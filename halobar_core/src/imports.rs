@@ -0,0 +1,17 @@
+pub use crate::{
+    client::{DataRequest, ProviderError},
+    modules::{ModuleData, RequestField},
+    types::{BiChannel, InstanceName, ModuleId},
+    R,
+};
+
+pub use ahash::AHashMap;
+pub use color_eyre::Report;
+pub use halobar_config::fmt::Truthy;
+pub use halogen::{Event, Status};
+pub use once_cell::sync::Lazy;
+pub use serde::{Deserialize, Serialize};
+pub use serde_repr::{Deserialize_repr, Serialize_repr};
+pub use std::{io, sync::Arc, time::Duration};
+pub use tokio::sync::mpsc;
+pub use tracing::error;
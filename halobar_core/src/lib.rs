@@ -1,14 +1,19 @@
-pub fn add(left: usize, right: usize) -> usize {
-    left + right
-}
+//! The data model that halobar's data providers and frontends share.
+//!
+//! Out-of-tree providers implement [`ModuleDataProvider`], and frontends consume the [`ModuleData`] that the providers send.
+#![allow(async_fn_in_trait)]
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+pub mod client;
+mod imports;
+pub mod modules;
+pub mod types;
 
-    #[test]
-    fn it_works() {
-        let result = add(2, 2);
-        assert_eq!(result, 4);
-    }
-}
+pub use client::{DataRequest, ErrorChain, ProviderError, Request};
+pub use modules::{
+    Data, ModuleData, ModuleDataProvider, ModuleMessage, ModuleOutput, ModuleType, ModuleYield,
+    RequestField,
+};
+pub use types::{BiChannel, InstanceName, ModuleId, ModuleIdInteger};
+
+/// The Result type that data providers return
+pub type R<T> = Result<T, color_eyre::Report>;
@@ -0,0 +1,231 @@
+pub mod command;
+pub mod plugin;
+pub mod time;
+pub mod upower;
+
+use crate::imports::*;
+
+/// A module that can be used in the backend to provide data.
+pub trait ModuleDataProvider: Sized + Send {
+    /// The type of input that the module requires to create a new instance,
+    /// including any type of config that the module requires for user customization.
+    ///
+    /// This must not have any required fields!!!
+    ///
+    /// This input is cloned before the module is created, so that duplicate modules/bars are not broken.
+    /// The module is responsible for ensuring this is not too expensive.
+    type ServerConfig: Clone;
+
+    /// Determine what fields a widget wants from this provider, given its format string.
+    ///
+    /// This is called by the runtime while it collects the [`DataRequest`]s from the bar config.
+    fn request_fields(format: &str) -> R<Vec<RequestField>>;
+
+    /// Determine what fields a widget's visibility rules need from this provider.
+    ///
+    /// By default, each field is requested like a variable in the format string.
+    fn rule_fields(fields: &[&str]) -> R<Vec<RequestField>> {
+        let format = fields
+            .iter()
+            .map(|f| format!("{{{f}}}"))
+            .collect::<String>();
+        Self::request_fields(&format)
+    }
+
+    /// This is the entry point for the data provider. This initializes it with its config,
+    /// its interface to the outside world, and a buffer that tells it what to watch for.
+    ///
+    /// It takes ownership of the data requests, fulfills them (or provides errors if it can't fulfull them),
+    /// and then passes the request vector back out with initial data values so the frontend can initialize whatever asked for data.
+    async fn main(
+        config: Self::ServerConfig,
+        requests: Vec<DataRequest>,
+        yield_channel: mpsc::UnboundedSender<ModuleYield>,
+    ) -> R<()>;
+}
+
+/// All the data that is yielded from each data provider.
+///
+/// It will provide a channel for events to be sent and data to be received if it
+/// is a dynamic module.
+///
+/// Additionally, it will give the initial data request vector back to the frontend.
+///
+/// This is required to tie it to the frontend.
+#[derive(Debug)]
+pub struct ModuleYield {
+    pub subscription: Option<BiChannel<ModuleMessage, ModuleOutput>>,
+    pub fulfilled_requests: Vec<DataRequest>,
+}
+
+/// A message that a dynamic module receives through its subscription while it is running.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ModuleMessage {
+    /// An event from one of the module's widgets
+    Event { widget: ModuleId, event: Event },
+    /// A widget started requesting these fields. The module must send their current data,
+    /// targeted at the widget, and keep it updated from now on.
+    Subscribe(DataRequest),
+    /// A widget stopped requesting these fields. The module can stop watching them if no other widget needs them.
+    Unsubscribe {
+        widget: ModuleId,
+        fields: Vec<RequestField>,
+    },
+}
+
+/// Something that a dynamic module sends through its subscription while it is running.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ModuleOutput {
+    /// New data for the widgets
+    Data(ModuleData),
+    /// The module failed to get data for a widget. If there is no `widget`, it is for every widget of the module.
+    ///
+    /// The widget shows the error until it receives new data.
+    Error {
+        widget: Option<ModuleId>,
+        error: ProviderError,
+    },
+}
+impl From<ModuleData> for ModuleOutput {
+    #[inline]
+    fn from(value: ModuleData) -> Self {
+        Self::Data(value)
+    }
+}
+
+// #[derive(Debug, Clone, PartialEq, Eq)]
+// pub enum Data {
+//     Time(time::TimeData),
+// }
+
+/// Content that can be sent to the frontend.
+///
+/// TODO: Finalize stuff required.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ModuleData {
+    pub specific_target: Option<ModuleId>,
+    pub content: Data,
+    // pub module_type: ModuleType,
+}
+impl ModuleData {
+    /// Create module data
+    #[inline]
+    pub const fn new(content: Data) -> Self {
+        Self {
+            specific_target: None,
+            content,
+        }
+    }
+}
+
+macro_rules! data_enum {
+    ($( [$module:ident] data_type: $( $data_type:ty ),+; request_field: $req_field_type:ty );+$(;)?) => {
+        /// The type of module. Should be tiny and contain nothing
+        #[derive(
+            Debug,
+            Clone,
+            PartialEq,
+            Eq,
+            PartialOrd,
+            Ord,
+            Hash,
+            Serialize,
+            Deserialize,
+            strum_macros::Display,
+            strum_macros::AsRefStr,
+            strum_macros::EnumString,
+        )]
+        #[strum(ascii_case_insensitive)]
+        pub enum ModuleType {
+            $( $module ),+
+        }
+
+        /// The data a module can provide. This is an enum, with a branch for each
+        /// data provider, and an inner tuple where the data is carried.
+        #[derive(Debug, Clone, PartialEq, Serialize, Deserialize, derive_more::From)]
+        pub enum Data {
+            $( $module($( $data_type ),+) ),+
+        }
+        impl Data {
+            /// Get the type of module that provides this data
+            pub fn module_type(&self) -> ModuleType {
+                match self {
+                    $( Self::$module(..) => ModuleType::$module ),+
+                }
+            }
+        }
+
+        /// These are the fields that you can request. This is sent to the providers.
+        #[derive(Debug, Clone, PartialEq, derive_more::From)]
+        pub enum RequestField {
+            $( $module($req_field_type) ),+
+        }
+    };
+}
+
+data_enum! {
+    [Time]
+    data_type: time::TimeData;
+    request_field: String;
+    [Upower]
+    data_type: upower::UpowerData;
+    request_field: upower::UpowerDataDiscriminants;
+    [Plugin]
+    data_type: plugin::PluginData;
+    request_field: plugin::PluginField;
+    [Command]
+    data_type: command::CommandData;
+    request_field: command::CommandField;
+}
+
+impl Data {
+    /// Get the status that this data implies, if it has one. The frontend uses this to highlight widgets.
+    pub fn status(&self) -> Option<Status> {
+        match self {
+            Self::Time(_) => None,
+            Self::Upower(d) => d.status(),
+            Self::Plugin(d) => d.status,
+            Self::Command(d) => d.status,
+        }
+    }
+
+    /// Get the name of the field that this data is for. Newer data for the same field replaces the old data.
+    pub fn field(&self) -> &'static str {
+        match self {
+            Self::Time(_) => "time",
+            Self::Upower(d) => upower::UpowerDataDiscriminants::from(d).into(),
+            Self::Plugin(d) => d.field.as_str(),
+            Self::Command(d) => d.field.as_str(),
+        }
+    }
+
+    /// Get the percentage that this data represents, if it is a percentage, like battery charge.
+    pub fn percentage(&self) -> Option<u8> {
+        match self {
+            Self::Upower(upower::UpowerData::Percentage(p)) => Some(p.get()),
+            Self::Command(d) => d.percentage(),
+            _ => None,
+        }
+    }
+}
+/// Data is shown the same way that it is in format strings.
+impl std::fmt::Display for Data {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Time(t) => f.write_str(&t.0),
+            Self::Upower(d) => d.fmt(f),
+            Self::Plugin(d) => d.fmt(f),
+            Self::Command(d) => d.fmt(f),
+        }
+    }
+}
+impl Truthy for Data {
+    fn is_truthy(&self) -> bool {
+        match self {
+            Self::Time(t) => t.0.is_truthy(),
+            Self::Upower(d) => d.is_truthy(),
+            Self::Plugin(d) => d.is_truthy(),
+            Self::Command(d) => d.is_truthy(),
+        }
+    }
+}
@@ -0,0 +1,86 @@
+//! The data that command modules provide from the output of shell commands.
+
+use halogen::Variant;
+
+use super::plugin::{fmt_variant, variant_is_truthy};
+use crate::imports::*;
+use crate::types::FieldName;
+
+/// A variable that a command provides in the format string.
+///
+/// Plain text output only provides `output` and `exit_status`, and JSON output provides a variable for each key of its objects.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, derive_more::Display)]
+#[serde(transparent)]
+pub struct CommandField(pub FieldName);
+impl CommandField {
+    /// The last line that the command printed, when its output is plain text
+    pub const OUTPUT: Self = Self(FieldName::from_static("output"));
+    /// The percentage in JSON output, which frontends can show as a gauge
+    pub const PERCENTAGE: Self = Self(FieldName::from_static("percentage"));
+    /// The exit status of the last run of the command, like `$?` in shells
+    pub const EXIT_STATUS: Self = Self(FieldName::from_static("exit_status"));
+
+    /// The fields that every command has, which are not interned
+    const BUILTIN: [Self; 3] = [Self::OUTPUT, Self::PERCENTAGE, Self::EXIT_STATUS];
+
+    /// Get the field with this name
+    #[inline]
+    pub fn new(name: &str) -> Self {
+        Self(FieldName::new(name))
+    }
+
+    #[inline]
+    pub fn as_str(&self) -> &'static str {
+        self.0.as_str()
+    }
+}
+/// Like [`FieldName`], only the names that were already created can be deserialized, besides the built-in fields.
+impl<'de> Deserialize<'de> for CommandField {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let name = std::borrow::Cow::<'de, str>::deserialize(deserializer)?;
+
+        if let Some(field) = Self::BUILTIN.into_iter().find(|f| f.as_str() == name) {
+            return Ok(field);
+        }
+        FieldName::get(&name)
+            .map(Self)
+            .ok_or_else(|| serde::de::Error::custom(format!("Unknown command field '{name}'")))
+    }
+}
+
+/// The value of a single variable from the last line that a command printed
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CommandData {
+    pub field: CommandField,
+    pub value: Variant,
+    /// The status of the line that this value is from, if the command sets one
+    pub status: Option<Status>,
+}
+impl CommandData {
+    /// Get the percentage that this value represents, if it is the `percentage` of JSON output.
+    pub fn percentage(&self) -> Option<u8> {
+        if self.field != CommandField::PERCENTAGE {
+            return None;
+        }
+
+        let percentage = match self.value {
+            Variant::Uint(u) => u,
+            Variant::Iint(i) => i.max(0) as u64,
+            Variant::Float(f) => f.max(0.0).round() as u64,
+            Variant::String(ref s) => s.trim().parse::<f64>().ok()?.max(0.0).round() as u64,
+            _ => return None,
+        };
+        Some(percentage.min(100) as u8)
+    }
+}
+/// Values are shown like the values that plugins send.
+impl std::fmt::Display for CommandData {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        fmt_variant(&self.value, f)
+    }
+}
+impl Truthy for CommandData {
+    fn is_truthy(&self) -> bool {
+        variant_is_truthy(&self.value)
+    }
+}
@@ -0,0 +1,77 @@
+//! The data that external plugins provide. See [`halogen::plugin`] for the protocol.
+
+use halogen::Variant;
+
+use crate::imports::*;
+use crate::types::FieldName;
+
+/// The name of a field that a plugin provides. Plugins declare their fields at runtime.
+pub type PluginField = FieldName;
+
+/// The value of a single field that a plugin sent
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PluginData {
+    pub field: PluginField,
+    pub value: Variant,
+    pub status: Option<Status>,
+}
+/// Lists are separated by spaces, and maps are shown as `key: value` pairs separated by commas.
+impl std::fmt::Display for PluginData {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        fmt_variant(&self.value, f)
+    }
+}
+/// Empty strings, lists and maps are falsy, and so is zero.
+impl Truthy for PluginData {
+    fn is_truthy(&self) -> bool {
+        variant_is_truthy(&self.value)
+    }
+}
+
+/// Show a value like it is shown in format strings
+pub(crate) fn fmt_variant(value: &Variant, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    match value {
+        Variant::String(s) | Variant::Other(s) => f.write_str(s),
+        Variant::Path(p) => write!(f, "{}", p.display()),
+        Variant::Bool(b) => write!(f, "{b}"),
+        Variant::Iint(i) => write!(f, "{i}"),
+        Variant::Uint(u) => write!(f, "{u}"),
+        Variant::Float(n) => write!(f, "{n}"),
+        Variant::Vec(items) => {
+            for (index, item) in items.iter().enumerate() {
+                if index > 0 {
+                    f.write_str(" ")?;
+                }
+                fmt_variant(item, f)?;
+            }
+            Ok(())
+        }
+        Variant::Map(map) => {
+            // The map is unordered, so the keys are sorted to keep the text stable
+            let mut entries = map.iter().collect::<Vec<_>>();
+            entries.sort_by(|a, b| a.0.cmp(b.0));
+
+            for (index, (key, item)) in entries.into_iter().enumerate() {
+                if index > 0 {
+                    f.write_str(", ")?;
+                }
+                write!(f, "{key}: ")?;
+                fmt_variant(item, f)?;
+            }
+            Ok(())
+        }
+    }
+}
+
+pub(crate) fn variant_is_truthy(value: &Variant) -> bool {
+    match value {
+        Variant::String(s) | Variant::Other(s) => s.is_truthy(),
+        Variant::Path(p) => !p.as_os_str().is_empty(),
+        Variant::Bool(b) => *b,
+        Variant::Iint(i) => i.is_truthy(),
+        Variant::Uint(u) => u.is_truthy(),
+        Variant::Float(n) => n.is_truthy(),
+        Variant::Vec(items) => !items.is_empty(),
+        Variant::Map(map) => !map.is_empty(),
+    }
+}
@@ -0,0 +1,7 @@
+//! The data that the time module provides.
+
+use crate::imports::*;
+
+/// The current time, already formatted with the widget's strftime format
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TimeData(pub String);
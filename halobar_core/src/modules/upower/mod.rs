@@ -0,0 +1,82 @@
+//! The data that the upower module provides about batteries and keyboard backlights.
+
+pub mod types;
+
+use crate::imports::*;
+use types::*;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, strum_macros::EnumDiscriminants)]
+#[strum_discriminants(derive(Hash, strum_macros::EnumString, strum_macros::IntoStaticStr))]
+#[strum_discriminants(strum(serialize_all = "snake_case"))]
+pub enum UpowerData {
+    Energy(f64),
+    EnergyRate(f64),
+    Icon(String),
+    Percentage(Percentage),
+    State(BatteryState),
+    Time(Duration),
+    /// Usually this can only change when using DisplayDevice.
+    /// It cannot change when the user selected a custom path.
+    DeviceType(DeviceType),
+    WarningLevel(WarningLevel),
+
+    CriticalAction(CriticalAction),
+    KeyboardBrightnessPercentage(Percentage),
+    KeyboardBrightness(i32),
+    KeyboardBrightnessMax(i32),
+}
+impl std::fmt::Display for UpowerData {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Energy(e) | Self::EnergyRate(e) => write!(f, "{e:.1}"),
+            Self::Icon(i) => f.write_str(i),
+            Self::Percentage(p) | Self::KeyboardBrightnessPercentage(p) => p.fmt(f),
+            Self::State(s) => s.fmt(f),
+            Self::Time(t) => {
+                let minutes = t.as_secs() / 60;
+                write!(f, "{}h {}m", minutes / 60, minutes % 60)
+            }
+            Self::DeviceType(d) => d.fmt(f),
+            Self::WarningLevel(w) => w.fmt(f),
+            Self::CriticalAction(c) => c.fmt(f),
+            Self::KeyboardBrightness(b) | Self::KeyboardBrightnessMax(b) => b.fmt(f),
+        }
+    }
+}
+/// Unknown values are falsy, and so is a warning level without a warning.
+impl Truthy for UpowerData {
+    fn is_truthy(&self) -> bool {
+        match self {
+            Self::Energy(e) | Self::EnergyRate(e) => e.is_truthy(),
+            Self::Icon(i) => i.is_truthy(),
+            Self::Percentage(p) | Self::KeyboardBrightnessPercentage(p) => p.get().is_truthy(),
+            Self::State(s) => *s != BatteryState::Unknown,
+            Self::Time(t) => !t.is_zero(),
+            Self::DeviceType(d) => *d != DeviceType::Unknown,
+            Self::WarningLevel(w) => !matches!(w, WarningLevel::Unknown | WarningLevel::None),
+            Self::CriticalAction(c) => *c != CriticalAction::Unknown,
+            Self::KeyboardBrightness(b) | Self::KeyboardBrightnessMax(b) => b.is_truthy(),
+        }
+    }
+}
+impl UpowerData {
+    /// Get the name of this field in format strings, along with its value formatted for display.
+    pub fn format_field(&self) -> (&'static str, String) {
+        let name = UpowerDataDiscriminants::from(self).into();
+        (name, self.to_string())
+    }
+
+    /// Get the status that this data implies. Only the warning level has a status.
+    pub fn status(&self) -> Option<Status> {
+        let Self::WarningLevel(level) = self else {
+            return None;
+        };
+
+        let status = match level {
+            WarningLevel::Unknown | WarningLevel::None => Status::Normal,
+            WarningLevel::Discharging | WarningLevel::Low => Status::Warn,
+            WarningLevel::Critical | WarningLevel::Action => Status::Critical,
+        };
+        Some(status)
+    }
+}
@@ -1,9 +1,14 @@
-use std::ops::Deref;
-
-use super::*;
+use crate::imports::*;
+#[cfg(feature = "zbus")]
+use std::{ops::Deref, str::FromStr};
+#[cfg(feature = "zbus")]
+use tracing::trace;
+#[cfg(feature = "zbus")]
+use zbus::zvariant::{self, Value};
 
 macro_rules! zvariant {
     ($type:ty => $enum:ty) => {
+        #[cfg(feature = "zbus")]
         impl From<::zbus::zvariant::OwnedValue> for $enum {
             fn from(value: ::zbus::zvariant::OwnedValue) -> Self {
                 match value.downcast_ref::<$type>() {
@@ -35,10 +40,10 @@ macro_rules! zvariant {
     strum_macros::FromRepr,
     strum_macros::AsRefStr,
     strum_macros::EnumString,
-    zvariant::Type,
     Deserialize_repr,
     Serialize_repr,
 )]
+#[cfg_attr(feature = "zbus", derive(zvariant::Type))]
 #[repr(u32)]
 #[strum(ascii_case_insensitive, serialize_all = "kebab-case")]
 pub enum BatteryState {
@@ -64,10 +69,10 @@ zvariant!(u32 => BatteryState);
     strum_macros::FromRepr,
     strum_macros::AsRefStr,
     strum_macros::EnumString,
-    zvariant::Type,
     Deserialize_repr,
     Serialize_repr,
 )]
+#[cfg_attr(feature = "zbus", derive(zvariant::Type))]
 #[repr(u32)]
 #[strum(ascii_case_insensitive, serialize_all = "kebab-case")]
 pub enum WarningLevel {
@@ -95,10 +100,10 @@ zvariant!(u32 => WarningLevel);
     strum_macros::FromRepr,
     strum_macros::AsRefStr,
     strum_macros::EnumString,
-    zvariant::Type,
     Deserialize_repr,
     Serialize_repr,
 )]
+#[cfg_attr(feature = "zbus", derive(zvariant::Type))]
 #[repr(u32)]
 pub enum DeviceType {
     #[default]
@@ -150,10 +155,10 @@ zvariant!(u32 => DeviceType);
     strum_macros::FromRepr,
     strum_macros::AsRefStr,
     strum_macros::EnumString,
-    zvariant::Type,
     Deserialize,
     Serialize,
 )]
+#[cfg_attr(feature = "zbus", derive(zvariant::Type))]
 pub enum CriticalAction {
     #[default]
     Unknown,
@@ -161,6 +166,7 @@ pub enum CriticalAction {
     Hibernate,
     PowerOff,
 }
+#[cfg(feature = "zbus")]
 impl TryFrom<::zbus::zvariant::OwnedValue> for CriticalAction {
     type Error = zvariant::Error;
     fn try_from(value: ::zbus::zvariant::OwnedValue) -> Result<Self, Self::Error> {
@@ -172,7 +178,17 @@ impl TryFrom<::zbus::zvariant::OwnedValue> for CriticalAction {
 }
 
 #[derive(
-    Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize, derive_more::AsRef,
+    Debug,
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+    Serialize,
+    Deserialize,
+    derive_more::AsRef,
+    derive_more::Display,
 )]
 pub struct Percentage(u8);
 impl Percentage {
@@ -188,6 +204,7 @@ impl Percentage {
         Ok(Self(input))
     }
 }
+#[cfg(feature = "zbus")]
 impl TryFrom<::zbus::zvariant::OwnedValue> for Percentage {
     type Error = ::zbus::zvariant::Error;
     fn try_from(value: ::zbus::zvariant::OwnedValue) -> Result<Self, Self::Error> {
@@ -1,4 +1,4 @@
-use crate::prelude::{error, Arc};
+use crate::imports::{error, Arc};
 
 /// A two-way mpmc channel.
 ///
@@ -0,0 +1,59 @@
+use std::sync::Mutex;
+
+use crate::imports::*;
+
+/// Every field name that was created at runtime. Names are never removed,
+/// so they are only created from the config, and everything else looks them up.
+static NAMES: Lazy<Mutex<ahash::AHashSet<&'static str>>> =
+    Lazy::new(|| Mutex::new(ahash::AHashSet::new()));
+
+/// The name of a field that is only known at runtime, like the fields that a plugin declares.
+///
+/// Data is keyed by `&'static str`, so each distinct name is interned the first time it is seen.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, derive_more::Display)]
+pub struct FieldName(&'static str);
+impl FieldName {
+    /// Get the field with this name, interning it if it is new.
+    ///
+    /// This should only be used for names from the config, like the variables of format strings.
+    pub fn new(name: &str) -> Self {
+        let mut names = NAMES.lock().unwrap_or_else(|e| e.into_inner());
+
+        if let Some(name) = names.get(name) {
+            return Self(name);
+        }
+
+        let name: &'static str = Box::leak(name.to_owned().into_boxed_str());
+        names.insert(name);
+        Self(name)
+    }
+
+    /// Get the field with this name if it was already created, without interning it
+    pub fn get(name: &str) -> Option<Self> {
+        let names = NAMES.lock().unwrap_or_else(|e| e.into_inner());
+        names.get(name).map(|name| Self(name))
+    }
+
+    /// Get the field with a name that is known at compile time, without interning it
+    #[inline]
+    pub const fn from_static(name: &'static str) -> Self {
+        Self(name)
+    }
+
+    #[inline]
+    pub fn as_str(&self) -> &'static str {
+        self.0
+    }
+}
+impl Serialize for FieldName {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.0)
+    }
+}
+/// Only names that were already created can be deserialized, because the data comes from outside of the config.
+impl<'de> Deserialize<'de> for FieldName {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let name = std::borrow::Cow::<'de, str>::deserialize(deserializer)?;
+        Self::get(&name).ok_or_else(|| serde::de::Error::custom(format!("Unknown field '{name}'")))
+    }
+}
@@ -0,0 +1,33 @@
+use crate::imports::{Deserialize, Serialize};
+
+/// The name of a module instance, like `clock_utc` in `[module.clock_utc]`.
+///
+/// Each instance runs its own data provider with its own config, so this is what the runtime uses to tell them apart.
+#[derive(
+    Debug,
+    Clone,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+    Hash,
+    Serialize,
+    Deserialize,
+    derive_more::Display,
+    derive_more::From,
+)]
+#[serde(transparent)]
+pub struct InstanceName(String);
+impl InstanceName {
+    /// Get the name as a string slice
+    #[inline]
+    pub fn as_str(&self) -> &str {
+        self.0.as_str()
+    }
+}
+impl From<&str> for InstanceName {
+    #[inline]
+    fn from(value: &str) -> Self {
+        Self(value.to_owned())
+    }
+}
@@ -0,0 +1,9 @@
+mod bichannel;
+mod field_name;
+mod instance_name;
+mod module_id;
+
+pub use bichannel::BiChannel;
+pub use field_name::FieldName;
+pub use instance_name::InstanceName;
+pub use module_id::{ModuleId, ModuleIdError, ModuleIdInteger};
@@ -0,0 +1,225 @@
+use std::sync::Mutex;
+
+use ahash::AHashSet;
+
+use crate::imports::{AHashMap, Arc, Deserialize, InstanceName, Lazy, Serialize};
+
+/// The raw integer type of the compact runtime handle in a [`ModuleId`]
+pub type ModuleIdInteger = u32;
+
+/// Every key that was turned into a [`ModuleId`], along with its handle.
+///
+/// Only the widgets that are declared, like in the config, are registered. Everything else looks them up.
+/// A widget keeps its handle when the config is reloaded, and the keys that the new config does not declare
+/// are removed with [`ModuleId::retain`], so this only grows with the widgets that are declared at once.
+static REGISTRY: Lazy<Mutex<Registry>> = Lazy::new(|| Mutex::new(Registry::default()));
+
+#[derive(Debug, Default)]
+struct Registry {
+    keys: AHashMap<Arc<str>, ModuleIdInteger>,
+    /// Handles are never reused, so an old ID can't be mistaken for a new widget
+    next: ModuleIdInteger,
+}
+
+/// The ID of a single widget on the bar.
+///
+/// It has a stable key that is derived from the config, like `halobar/clock_utc`,
+/// so recordings and external tools can refer to the same widget across restarts.
+/// It also has a compact handle that is only valid while the bar is running, which is what it is compared by.
+///
+/// It is serialized as its key. Only the keys of widgets that exist can be deserialized.
+#[derive(Debug, Clone)]
+pub struct ModuleId {
+    handle: ModuleIdInteger,
+    key: Arc<str>,
+}
+impl ModuleId {
+    /// Get the ID with this key, registering it if it is new. The same key always gets the same handle.
+    ///
+    /// This is meant for widgets that are declared, like in the config. Use [`ModuleId::get`] for keys from anywhere else.
+    pub fn new(key: &str) -> Result<Self, ModuleIdError> {
+        let mut registry = REGISTRY.lock().unwrap_or_else(|e| e.into_inner());
+
+        if let Some((key, handle)) = registry.keys.get_key_value(key) {
+            return Ok(Self {
+                handle: *handle,
+                key: Arc::clone(key),
+            });
+        }
+
+        let handle = registry.next;
+        registry.next = handle.checked_add(1).ok_or(ModuleIdError::OutOfHandles)?;
+        let key: Arc<str> = Arc::from(key);
+        registry.keys.insert(Arc::clone(&key), handle);
+
+        Ok(Self { handle, key })
+    }
+
+    /// Forget every registered key except the ones of these widgets, like when a reloaded config declares others.
+    ///
+    /// The IDs that are still around keep working, but their keys can't be looked up anymore.
+    /// If one of them is declared again, it gets a new handle.
+    pub fn retain<'a>(declared: impl IntoIterator<Item = &'a ModuleId>) {
+        let declared = declared
+            .into_iter()
+            .map(|id| id.handle)
+            .collect::<AHashSet<_>>();
+
+        let mut registry = REGISTRY.lock().unwrap_or_else(|e| e.into_inner());
+        registry.keys.retain(|_, handle| declared.contains(handle));
+    }
+
+    /// Get the ID of a widget that was already registered
+    pub fn get(key: &str) -> Result<Self, ModuleIdError> {
+        let registry = REGISTRY.lock().unwrap_or_else(|e| e.into_inner());
+
+        match registry.keys.get_key_value(key) {
+            Some((key, handle)) => Ok(Self {
+                handle: *handle,
+                key: Arc::clone(key),
+            }),
+            None => Err(ModuleIdError::Unknown(key.to_owned())),
+        }
+    }
+
+    /// Deserialize the key of a widget that is being declared, registering it if it is new.
+    ///
+    /// This is for layouts, which declare their widgets like the config does, as in recordings.
+    pub fn deserialize_declared<'de, D: serde::Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Self, D::Error> {
+        let key = std::borrow::Cow::<'de, str>::deserialize(deserializer)?;
+        Self::new(&key).map_err(serde::de::Error::custom)
+    }
+
+    /// Get the ID of a widget on a bar.
+    ///
+    /// The first widget of each module instance is keyed by the bar and instance names, like `halobar/time`.
+    /// If a module instance has several widgets, the others need a `discriminator`, like `halobar/time/1`.
+    pub fn for_widget(
+        bar: &str,
+        instance: &InstanceName,
+        discriminator: Option<&str>,
+    ) -> Result<Self, ModuleIdError> {
+        match discriminator {
+            Some(d) => Self::new(&format!("{bar}/{instance}/{d}")),
+            None => Self::new(&format!("{bar}/{instance}")),
+        }
+    }
+
+    /// Get the stable key
+    #[inline]
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    /// Get the runtime handle. This is not stable across restarts.
+    #[inline]
+    pub fn handle(&self) -> ModuleIdInteger {
+        self.handle
+    }
+}
+impl PartialEq for ModuleId {
+    #[inline]
+    fn eq(&self, other: &Self) -> bool {
+        self.handle == other.handle
+    }
+}
+impl Eq for ModuleId {}
+impl std::hash::Hash for ModuleId {
+    #[inline]
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.handle.hash(state)
+    }
+}
+impl PartialOrd for ModuleId {
+    #[inline]
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+impl Ord for ModuleId {
+    #[inline]
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.handle.cmp(&other.handle)
+    }
+}
+impl std::fmt::Display for ModuleId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.key.fmt(f)
+    }
+}
+impl Serialize for ModuleId {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.key)
+    }
+}
+impl<'de> Deserialize<'de> for ModuleId {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let key = std::borrow::Cow::<'de, str>::deserialize(deserializer)?;
+        Self::get(&key).map_err(serde::de::Error::custom)
+    }
+}
+
+/// An error when getting a [`ModuleId`]
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum ModuleIdError {
+    /// Every handle is taken, which only happens if something keeps registering new widgets
+    #[error("Ran out of module ID handles")]
+    OutOfHandles,
+    #[error("Unknown widget: {0}")]
+    Unknown(String),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Forgetting keys affects the whole registry, so these can't run in parallel
+    static REGISTRY_TEST: Mutex<()> = Mutex::new(());
+
+    #[test]
+    fn keys_are_stable() {
+        let _guard = REGISTRY_TEST.lock().unwrap_or_else(|e| e.into_inner());
+
+        let instance = InstanceName::from("clock_utc");
+        let first = ModuleId::for_widget("test_bar", &instance, None).unwrap();
+        let second = ModuleId::for_widget("test_bar", &instance, Some("1")).unwrap();
+
+        assert_eq!(first.key(), "test_bar/clock_utc");
+        assert_ne!(first, second);
+        assert_eq!(
+            ModuleId::get("test_bar/clock_utc").unwrap().handle(),
+            first.handle()
+        );
+
+        let json = serde_json::to_string(&second).unwrap();
+        assert_eq!(json, "\"test_bar/clock_utc/1\"");
+        assert_eq!(serde_json::from_str::<ModuleId>(&json).unwrap(), second);
+
+        // Only widgets that exist can be deserialized
+        assert!(serde_json::from_str::<ModuleId>("\"test_bar/clock_utc/2\"").is_err());
+        assert!(ModuleId::get("test_bar/clock_utc/2").is_err());
+    }
+
+    #[test]
+    fn forgets_undeclared_keys() {
+        let _guard = REGISTRY_TEST.lock().unwrap_or_else(|e| e.into_inner());
+
+        let kept = ModuleId::new("test_bar/kept").unwrap();
+        let renamed = ModuleId::new("test_bar/renamed").unwrap();
+
+        ModuleId::retain([&kept]);
+
+        assert_eq!(
+            ModuleId::get("test_bar/kept").unwrap().handle(),
+            kept.handle()
+        );
+        assert!(ModuleId::get("test_bar/renamed").is_err());
+
+        // Declaring it again does not reuse a handle that is still around
+        let declared_again = ModuleId::new("test_bar/renamed").unwrap();
+        assert_ne!(declared_again, renamed);
+        assert_ne!(declared_again, kept);
+    }
+}
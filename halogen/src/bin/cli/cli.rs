@@ -18,6 +18,11 @@ pub struct Cli {
         long_help = "Start halogen in SERVER mode. Please note that there can only be one server per socket!"
     )]
     pub server: bool,
+    /// Ask the running server to shut down gracefully
+    #[arg(long, conflicts_with = "server")]
+    pub shutdown: bool,
+    #[command(subcommand)]
+    pub command: Option<Command>,
 }
 impl Cli {
     /// Create a new [`Cli`], parsing args and doing other misc tasks
@@ -30,6 +35,15 @@ impl Cli {
 
 #[derive(Debug, Subcommand)]
 pub enum Command {
-    Server,
-    Msg {},
+    /// List the modules of the running bar, with their state and statistics
+    Modules {
+        /// Print JSON instead of a table
+        #[arg(long)]
+        json: bool,
+    },
+    /// Run the polling commands of the running bar that have this key again right away
+    Refresh {
+        /// The `key` of the command modules
+        key: String,
+    },
 }
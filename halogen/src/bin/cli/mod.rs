@@ -1,4 +1,6 @@
 pub mod cli;
+pub mod modules;
+pub mod refresh;
 
 pub use color_eyre::eyre::{bail, Report};
 pub use halogen::imports::*;
@@ -6,54 +8,88 @@ pub use tracing::info;
 
 pub type R<T> = color_eyre::Result<T>;
 
-pub async fn async_main(cli: cli::Cli) -> R<()> {
+pub async fn async_main(cli: cli::Cli) -> R<i32> {
     let (mut interface, stub) =
-        halogen::interface::Interface::new(cli.logconfig.logfile.as_deref()).await?;
+        halogen::interface::Interface::new(cli.socket_path.as_deref()).await?;
+
+    info!("{:#?}", cli);
+
+    if cli.shutdown {
+        let message = halogen::Message {
+            sender_type: halogen::Target::Control {
+                command: halogen::Command::Shutdown,
+            },
+            identifier: "shutdown".to_owned(),
+            ..Default::default()
+        };
+        interface.send_once(&message).await?;
+        info!(
+            "Asked the server at {} to shut down",
+            interface.path().display()
+        );
+        return Ok(0);
+    }
+
+    match cli.command {
+        Some(cli::Command::Modules { json }) => return modules::print(&mut interface, json).await,
+        Some(cli::Command::Refresh { key }) => return refresh::refresh(&mut interface, key).await,
+        None => {}
+    }
+
+    if !cli.server {
+        return Ok(0);
+    }
 
     let server_handle = tokio::spawn(async move {
-        if cli.server {
-            match interface.server().await {
-                Ok(()) => tracing::error!("Server ended early!"),
-                Err(e) => tracing::error!("Server error: {e}"),
-            }
-            panic!("Server loop quit unexpectedly!");
+        match interface.server().await {
+            Ok(()) => tracing::error!("Server ended early!"),
+            Err(e) => tracing::error!("Server error: {e}"),
         }
+        panic!("Server loop quit unexpectedly!");
     });
 
-    info!("{:#?}", cli);
+    let code = tokio::select! {
+        join = server_handle => {
+            join?;
+            1
+        }
+        code = server_signal_handler(stub) => code,
+    };
 
-    server_handle.await?;
-    Ok::<(), color_eyre::eyre::Report>(())
+    Ok(code)
 }
 
 /// ONLY CALL THIS IF THE INTERFACE IS A SERVER!!!
-pub async fn server_signal_handler(interface: halogen::interface::InterfaceStub) -> ! {
+///
+/// Waits for a shutdown signal, removes the socket, then returns the exit code for that signal.
+pub async fn server_signal_handler(interface: halogen::interface::InterfaceStub) -> i32 {
     macro_rules! signals {
         ($( $sigtype:tt ),+) => {
-            [$( (::tokio::signal::unix::SignalKind::$sigtype(), stringify!($sigtyp)) ),+]
+            [$( (::tokio::signal::unix::SignalKind::$sigtype(), stringify!($sigtype)) ),+]
         };
     }
     let mut signals = signals![interrupt, quit, terminate]
         .map(|(sig, name)| {
             // Safety: This is an error that I can catch in development
             let mut signal_recv = tokio::signal::unix::signal(sig).unwrap();
-            let int = interface.clone_of_arc();
 
             async move {
                 let _ = signal_recv.recv().await;
-                // safety: We are a server
-                if let Err(e) = int.drop_path() {
-                    error!("Error while trying to drop socket path: {e}");
-                }
-
-                warn!("Received signal: {name}, shutting down...");
-                std::process::exit(sig.as_raw_value())
+                (sig, name)
             }
         })
         .into_iter()
         .collect::<FuturesUnordered<_>>();
 
-    loop {
-        signals.next().await;
+    // There are always signals in here, so this can't be None
+    let (sig, name) = signals.next().await.unwrap();
+    warn!("Received signal: {name}, shutting down...");
+
+    // safety: We are a server
+    if let Err(e) = interface.drop_path() {
+        error!("Error while trying to drop socket path: {e}");
     }
+
+    // The conventional exit code for being killed by a signal
+    128 + sig.as_raw_value()
 }
@@ -0,0 +1,105 @@
+use super::*;
+use halogen::{ModuleInfo, Reply, Target};
+
+/// Ask the bar for the state of its modules, then print them as a table or JSON.
+pub async fn print(interface: &mut halogen::interface::Interface, as_json: bool) -> R<i32> {
+    let message = halogen::Message {
+        sender_type: Target::Control {
+            command: halogen::Command::Introspect,
+        },
+        identifier: "introspect".to_owned(),
+        ..Default::default()
+    };
+
+    let reply = interface.request(&message).await?;
+    let Target::Reply {
+        reply: Reply::Introspection { modules },
+    } = reply.sender_type
+    else {
+        bail!(
+            "The server sent an unexpected reply: {:?}",
+            reply.sender_type
+        );
+    };
+
+    if as_json {
+        println!("{}", json::to_string_pretty(&modules)?);
+    } else {
+        print_table(&modules);
+    }
+
+    Ok(0)
+}
+
+fn print_table(modules: &[ModuleInfo]) {
+    const HEADER: [&str; 11] = [
+        "INSTANCE",
+        "TYPE",
+        "STATE",
+        "UPTIME",
+        "RESTARTS",
+        "MSGS",
+        "MSGS/MIN",
+        "WIDGETS",
+        "LAST DATA",
+        "LAST ERROR",
+        "CONFIG",
+    ];
+
+    let rows = modules
+        .iter()
+        .map(|m| {
+            [
+                m.instance.clone(),
+                m.module_type.clone(),
+                m.state.to_string(),
+                m.uptime_ms
+                    .map(format_uptime)
+                    .unwrap_or_else(|| "-".to_owned()),
+                m.restarts.to_string(),
+                m.messages.to_string(),
+                m.messages_last_minute.to_string(),
+                m.widgets.join(","),
+                m.last_data.clone().unwrap_or_else(|| "-".to_owned()),
+                m.last_error.clone().unwrap_or_else(|| "-".to_owned()),
+                m.config.clone(),
+            ]
+        })
+        .collect::<Vec<_>>();
+
+    let mut widths = HEADER.map(str::len);
+    for row in rows.iter() {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+
+    let print_row = |cells: &mut dyn Iterator<Item = &str>| {
+        let line = cells
+            .zip(widths)
+            .map(|(cell, width)| format!("{cell:<width$}"))
+            .collect::<Vec<_>>()
+            .join("  ");
+        println!("{}", line.trim_end());
+    };
+
+    print_row(&mut HEADER.into_iter());
+    for row in rows.iter() {
+        print_row(&mut row.iter().map(String::as_str));
+    }
+}
+
+/// Format an uptime like `1h 2m 3s`
+fn format_uptime(ms: u64) -> String {
+    let seconds = ms / 1000;
+    match seconds {
+        0..=59 => format!("{seconds}s"),
+        60..=3599 => format!("{}m {}s", seconds / 60, seconds % 60),
+        _ => format!(
+            "{}h {}m {}s",
+            seconds / 3600,
+            (seconds / 60) % 60,
+            seconds % 60
+        ),
+    }
+}
@@ -0,0 +1,32 @@
+use super::*;
+use halogen::{Reply, Target};
+
+/// Ask the bar to run the polling commands with this key again, then print how many it refreshed.
+pub async fn refresh(interface: &mut halogen::interface::Interface, key: String) -> R<i32> {
+    let message = halogen::Message {
+        sender_type: Target::Control {
+            command: halogen::Command::Refresh { key: key.clone() },
+        },
+        identifier: "refresh".to_owned(),
+        ..Default::default()
+    };
+
+    let reply = interface.request(&message).await?;
+    let Target::Reply {
+        reply: Reply::Refreshed { count },
+    } = reply.sender_type
+    else {
+        bail!(
+            "The server sent an unexpected reply: {:?}",
+            reply.sender_type
+        );
+    };
+
+    if count == 0 {
+        println!("No polling command has the key '{key}'");
+        return Ok(1);
+    }
+
+    println!("Refreshed {count} command(s) with the key '{key}'");
+    Ok(0)
+}
@@ -3,7 +3,9 @@ pub mod cli;
 fn main() -> cli::R<()> {
     color_eyre::install()?;
 
-    let cli = cli::cli::Cli::new();
+    let mut cli = cli::cli::Cli::new();
+    // Subcommands print their output to stdout
+    cli.logconfig.stderr |= cli.command.is_some();
 
     halogen::complete::init_log(&cli.logconfig, []);
 
@@ -11,7 +13,9 @@ fn main() -> cli::R<()> {
         .enable_all()
         .build()?;
 
-    rt.block_on(async move { cli::async_main(cli).await })?;
+    let code = rt.block_on(async move { cli::async_main(cli).await })?;
 
-    Ok(())
+    // Statics are not dropped on exit, so the logfile has to be flushed by hand
+    halogen::complete::flush_log();
+    std::process::exit(code)
 }
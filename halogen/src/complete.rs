@@ -1,11 +1,14 @@
 use clap::{Args, ValueEnum};
-use once_cell::sync::OnceCell;
 use tracing::level_filters::LevelFilter;
 
 pub(crate) use crate::imports::*;
 use std::fs;
 use std::io::IsTerminal;
-use tracing_subscriber::{fmt::format::FmtSpan, prelude::*};
+use std::sync::Mutex;
+use tracing_subscriber::{
+    fmt::{format::FmtSpan, writer::BoxMakeWriter},
+    prelude::*,
+};
 
 /// The configuration for logging
 #[derive(Debug, Default, Args)]
@@ -19,6 +22,9 @@ pub struct LogConfig {
     /// Log to file as well as output
     #[arg(verbatim_doc_comment, long)]
     pub logfile: Option<PathBuf>,
+    /// Log to stderr instead of stdout. This is for programs that use stdout for their own output.
+    #[arg(skip)]
+    pub stderr: bool,
 }
 
 /// The console output of my choice
@@ -27,7 +33,7 @@ pub fn console() -> std::io::Stdout {
     std::io::stdout()
 }
 
-static WORKER_GUARD: OnceCell<tracing_appender::non_blocking::WorkerGuard> = OnceCell::new();
+static WORKER_GUARD: Mutex<Option<tracing_appender::non_blocking::WorkerGuard>> = Mutex::new(None);
 
 /// The maximum number of queued messages to keep before dropping
 pub const MAX_QUEUED_MESSAGES: usize = 64;
@@ -45,7 +51,14 @@ pub fn init_log<const N: usize>(config: &LogConfig, nice_targets: [&str; N]) {
     let Some(tracing_level) = config.level.tracing() else {
         return;
     };
-    let should_color = config.color.should_color();
+    let (writer, is_terminal) = match config.stderr {
+        true => (
+            BoxMakeWriter::new(std::io::stderr),
+            std::io::stderr().is_terminal(),
+        ),
+        false => (BoxMakeWriter::new(console), console().is_terminal()),
+    };
+    let should_color = config.color.should_color_for(is_terminal);
 
     let subscriber = tracing_subscriber::fmt()
         .with_max_level(tracing_level)
@@ -56,7 +69,7 @@ pub fn init_log<const N: usize>(config: &LogConfig, nice_targets: [&str; N]) {
         .with_target(true)
         .with_thread_ids(true)
         .with_ansi(should_color)
-        .with_writer(console)
+        .with_writer(writer)
         .finish();
 
     let nice_filter = config.level.nice_level_filter();
@@ -79,9 +92,10 @@ pub fn init_log<const N: usize>(config: &LogConfig, nice_targets: [&str; N]) {
                         .lossy(true)
                         .finish(f);
 
-                WORKER_GUARD
-                    .set(guard)
-                    .expect("Could not set the global tracing_appender worker guard!");
+                *WORKER_GUARD
+                    .lock()
+                    .expect("Could not set the global tracing_appender worker guard!") =
+                    Some(guard);
 
                 tracing_subscriber::fmt::layer()
                     .with_ansi(false)
@@ -109,6 +123,19 @@ pub fn init_log<const N: usize>(config: &LogConfig, nice_targets: [&str; N]) {
     return;
 }
 
+/// Flush the logfile, if there is one. Nothing is written to the logfile after this.
+///
+/// Call this right before exiting, because statics are not dropped when the process exits.
+pub fn flush_log() {
+    let guard = match WORKER_GUARD.lock() {
+        Ok(mut g) => g.take(),
+        Err(e) => e.into_inner().take(),
+    };
+
+    // The guard flushes the remaining lines when it is dropped
+    drop(guard);
+}
+
 /// Choose when to color the terminal output
 #[derive(
     Debug,
@@ -140,9 +167,13 @@ pub enum ColorOption {
 impl ColorOption {
     /// Determine if the logging output should show ansi colors
     pub fn should_color(&self) -> bool {
+        self.should_color_for(console().is_terminal())
+    }
+    /// Determine if the logging output should show ansi colors, given whether it is a terminal or not
+    pub fn should_color_for(&self, is_terminal: bool) -> bool {
         match self {
             Self::Always => true,
-            Self::Auto => console().is_terminal(),
+            Self::Auto => is_terminal,
             Self::Never => false,
         }
     }
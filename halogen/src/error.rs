@@ -23,6 +23,8 @@ pub enum Error {
     InvalidState(crate::interface::InterfaceState),
     /// Received a message from an unknown API version
     InvalidApiVersion(u8),
+    /// The other end of the socket closed the connection
+    Disconnected,
     /// Other errors that don't really fit well
     Internal(&'static str),
 }
@@ -40,6 +42,7 @@ impl std::fmt::Display for Error {
             Self::JoinError(e) => e.fmt(f),
             Self::InvalidState(s) => write!(f, "Invalid interface state: {s:?}"),
             Self::InvalidApiVersion(v) => write!(f, "Invalid API version: {v}"),
+            Self::Disconnected => "Socket disconnected".fmt(f),
             Self::Internal(e) => e.fmt(f),
         }
     }
@@ -1,10 +1,17 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+
 use futures_util::{stream::FuturesOrdered, TryStreamExt};
 
 use crate::imports::*;
+use crate::Target;
 
 /// The max size of the chunks that I read from the socket
 const BUFFER_SIZE: usize = 2048;
 
+/// The number of requests this process made, so each one gets a unique identifier
+static REQUESTS: AtomicUsize = AtomicUsize::new(0);
+
 /// The interface for a socket. This should be a singleton.
 #[derive(Debug)]
 pub struct Interface {
@@ -81,6 +88,55 @@ impl Interface {
 
         Err(Error::EarlyReturn)
     }
+    /// Connect to the server as a client, send a single message, then disconnect.
+    ///
+    /// This is for one-off commands, like asking the server to shut down.
+    pub async fn send_once(&mut self, message: &Message) -> Result<(), Error> {
+        self.state.try_as(InterfaceType::Client)?;
+
+        let stream = UnixStream::connect(self.path()).await?;
+        Self::write_message(&stream, message).await
+    }
+    /// Connect to the server as a client, send a single message, then wait for the reply to it.
+    ///
+    /// The identifier of the message is made unique, so the reply can't be mixed up with anything else the server sends.
+    pub async fn request(&mut self, message: &Message) -> Result<Message, Error> {
+        self.state.try_as(InterfaceType::Client)?;
+
+        let mut message = message.clone();
+        message.identifier = format!(
+            "{}/{}/{}",
+            message.identifier,
+            std::process::id(),
+            REQUESTS.fetch_add(1, Ordering::Relaxed)
+        );
+
+        let stream = UnixStream::connect(self.path()).await?;
+        Self::write_message(&stream, &message).await?;
+
+        let (sender, receiver) = flume::unbounded();
+        let sender = Arc::new(sender);
+
+        let reply = async {
+            loop {
+                let reply: Message = receiver.recv_async().await?;
+                if matches!(reply.sender_type, Target::Reply { .. })
+                    && reply.identifier == message.identifier
+                {
+                    return Ok(reply);
+                }
+                trace!("Ignoring halogen message '{}'", reply.identifier);
+            }
+        };
+
+        tokio::select! {
+            read = Self::read_socket_forever(sender, &stream) => {
+                read?;
+                Err(Error::EarlyReturn)
+            }
+            reply = reply => reply,
+        }
+    }
     /// Act as a server for the socket.
     ///
     /// There can be only one server accepting connections, this will return an error if there is already one.
@@ -92,6 +148,12 @@ impl Interface {
         let socket = UnixListener::bind(self.path())?;
         let mut handles = FuturesUnordered::new();
 
+        let connections = Arc::new(Mutex::new(Connections::default()));
+        let dispatcher = tokio::spawn(Self::dispatch_forever(
+            Arc::clone(&self.sock_receiver),
+            Arc::clone(&connections),
+        ));
+
         loop {
             let (stream, address) = match socket.accept().await {
                 Ok(s) => s,
@@ -105,27 +167,70 @@ impl Interface {
                 address.as_pathname()
             );
             let owned_sender = Arc::clone(&self.sub_sender);
-            let owned_receiver = Arc::clone(&self.sock_receiver);
+            let connections = Arc::clone(&connections);
             let handle = tokio::spawn(async move {
+                let (id, outgoing) = lock(&connections).add();
+                let (incoming_sender, incoming) = flume::unbounded();
+
+                // The reader drops its sender when the client disconnects, and the messages that it read before
+                // are still forwarded, like the message of a client that disconnects right after sending it.
+                let read_and_forward = async {
+                    let (read, forward) = tokio::join!(
+                        Self::read_socket_forever(Arc::new(incoming_sender), &stream),
+                        Self::forward_forever(id, &incoming, &owned_sender, &connections),
+                    );
+                    read.and(forward)
+                };
                 let res = tokio::try_join!(
-                    Self::read_socket_forever(owned_sender, &stream),
-                    Self::write_socket_forever(owned_receiver, &stream)
+                    read_and_forward,
+                    Self::write_socket_forever(Arc::new(outgoing), &stream),
                 );
+                lock(&connections).remove(id);
 
                 match res {
                     Ok(((), ())) => unreachable!(),
+                    Err(Error::Disconnected) => debug!("Halogen client disconnected"),
                     Err(e) => error!("{e}"),
                 }
             });
             handles.push(handle);
         }
 
+        dispatcher.abort();
         while let Some(join) = handles.next().await {
             join?;
         }
 
         Err(Error::EarlyReturn)
     }
+    /// Send each message from the server's side to the clients that it is meant for
+    async fn dispatch_forever(
+        receiver: Arc<flume::Receiver<Message>>,
+        connections: Arc<Mutex<Connections>>,
+    ) {
+        while let Ok(message) = receiver.recv_async().await {
+            lock(&connections).dispatch(message);
+        }
+    }
+
+    /// Pass the messages that a client sent on to the server's side, remembering which requests it is waiting on
+    async fn forward_forever(
+        id: usize,
+        receiver: &flume::Receiver<Message>,
+        sender: &flume::Sender<Message>,
+        connections: &Mutex<Connections>,
+    ) -> Result<(), Error> {
+        loop {
+            let message = receiver.recv_async().await?;
+            if let Target::Control { ref command } = message.sender_type {
+                if command.has_reply() {
+                    lock(connections).expect_reply(id, &message.identifier);
+                }
+            }
+            sender.send_async(message).await?;
+        }
+    }
+
     #[instrument(level = "debug", skip_all)]
     async fn write_socket_forever(
         receiver: Arc<flume::Receiver<Message>>,
@@ -133,13 +238,19 @@ impl Interface {
     ) -> Result<(), Error> {
         loop {
             let message = receiver.recv_async().await?;
-            if let Some(message_serialized) = format_message_for_sender(&message) {
-                stream.writable().await?;
-                stream.try_write(message_serialized.as_slice())?;
-            }
+            Self::write_message(stream, &message).await?;
         }
     }
 
+    /// Write a single message to the socket
+    async fn write_message(stream: &UnixStream, message: &Message) -> Result<(), Error> {
+        if let Some(message_serialized) = format_message_for_sender(message) {
+            stream.writable().await?;
+            stream.try_write(message_serialized.as_slice())?;
+        }
+        Ok(())
+    }
+
     // #[instrument(level = "debug", skip_all)]
     async fn read_socket_forever(
         sender: Arc<flume::Sender<Message>>,
@@ -151,12 +262,17 @@ impl Interface {
             stream.readable().await?;
             let mut read_buffer = [0; BUFFER_SIZE];
 
-            // It is okay to loop like this, that function joins multiple futures that should all be in order.
-            while stream.try_read(&mut read_buffer)? > 0 {
-                deserialize_bytes(&mut line_buffer, read_buffer, &sender).await?;
-                // if something weird happens with my channel and it failed, that would be a shame
-                stream.readable().await?;
-            }
+            let read_bytes = match stream.try_read(&mut read_buffer) {
+                // Reading nothing from a readable socket means it was closed
+                Ok(0) => return Err(Error::Disconnected),
+                Ok(n) => n,
+                // readable() can have false positives
+                Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => continue,
+                Err(e) => return Err(e.into()),
+            };
+
+            // It is okay to await this here, that function joins multiple futures that should all be in order.
+            deserialize_bytes(&mut line_buffer, &read_buffer[..read_bytes], &sender).await?;
         }
     }
     /// Get the socket path
@@ -164,9 +280,19 @@ impl Interface {
     pub fn path<'p>(&'p self) -> &'p Path {
         &self.socket_path
     }
+    /// Get the current state of the interface. It can only be a server if the socket did not exist when it was created.
+    #[inline]
+    pub fn state(&self) -> InterfaceState {
+        self.state
+    }
 }
 impl Drop for Interface {
     fn drop(&mut self) {
+        // Clients do not own the socket
+        if self.state != InterfaceState::Current(InterfaceType::Server) {
+            return;
+        }
+
         match remove_socket(self.socket_path.as_path(), self.state) {
             Ok(_) => debug!(
                 "Interface removed socket path: {}",
@@ -180,11 +306,91 @@ impl Drop for Interface {
     }
 }
 
+/// Lock the connections, even if a connection task panicked while holding them
+fn lock(connections: &Mutex<Connections>) -> std::sync::MutexGuard<'_, Connections> {
+    connections.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// The clients that are connected to a server.
+///
+/// A reply only goes to the client that sent the request, and everything else goes to every client.
+#[derive(Debug, Default)]
+struct Connections {
+    next_id: usize,
+    clients: Vec<Connection>,
+}
+#[derive(Debug)]
+struct Connection {
+    id: usize,
+    sender: flume::Sender<Message>,
+    /// The identifiers of the control messages that the client sent, which it might be waiting on replies to
+    requests: Vec<String>,
+}
+impl Connections {
+    /// Add a client, returning its id and the receiver of the messages for it
+    fn add(&mut self) -> (usize, flume::Receiver<Message>) {
+        let (sender, receiver) = flume::unbounded();
+        let id = self.next_id;
+        self.next_id += 1;
+
+        self.clients.push(Connection {
+            id,
+            sender,
+            requests: Vec::new(),
+        });
+        (id, receiver)
+    }
+
+    fn remove(&mut self, id: usize) {
+        self.clients.retain(|c| c.id != id);
+    }
+
+    /// Remember that this client sent a request, so the reply to it goes to this client
+    fn expect_reply(&mut self, id: usize, identifier: &str) {
+        if let Some(client) = self.clients.iter_mut().find(|c| c.id == id) {
+            client.requests.push(identifier.to_owned());
+        }
+    }
+
+    fn dispatch(&mut self, message: Message) {
+        if !matches!(message.sender_type, Target::Reply { .. }) {
+            self.clients
+                .retain(|client| client.sender.send(message.clone()).is_ok());
+            return;
+        }
+
+        let requester = self.clients.iter_mut().find_map(|client| {
+            let index = client
+                .requests
+                .iter()
+                .position(|r| *r == message.identifier)?;
+            client.requests.swap_remove(index);
+            Some(client)
+        });
+
+        match requester {
+            Some(client) => {
+                if client.sender.send(message).is_err() {
+                    debug!("Halogen client disconnected before it got its reply");
+                }
+            }
+            None => warn!(
+                "Dropping halogen reply '{}', no client is waiting on it",
+                message.identifier
+            ),
+        }
+    }
+}
+
 /// Remove the socketfile. This is used internally by any server impl.
 #[instrument(level = "debug", skip_all)]
 fn remove_socket(socket: &Path, interface_state: InterfaceState) -> Result<(), Error> {
     if interface_state == InterfaceState::Current(InterfaceType::Server) {
-        std::fs::remove_file(socket)?;
+        match std::fs::remove_file(socket) {
+            // It might have been removed through an InterfaceStub already
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            other => other?,
+        }
     } else {
         return Err(Error::InvalidState(interface_state));
     }
@@ -272,7 +478,7 @@ impl InterfaceStub {
 ///
 /// Trust me, you don't wanna know any more than this.
 #[instrument(level = "trace", skip_all)]
-fn format_message_for_sender(message: &Message) -> Option<Vec<u8>> {
+pub(crate) fn format_message_for_sender<T: Serialize>(message: &T) -> Option<Vec<u8>> {
     let mut buffer = match json::to_vec(message) {
         Ok(b) => b,
         Err(e) => {
@@ -300,32 +506,34 @@ fn format_message_for_sender(message: &Message) -> Option<Vec<u8>> {
 #[instrument(level = "trace", skip_all)]
 async fn deserialize_bytes(
     partial_message: &mut SmallVec<[u8; BUFFER_SIZE]>,
-    bytes: [u8; BUFFER_SIZE],
+    bytes: &[u8],
     sender: &flume::Sender<Message>,
 ) -> Result<(), Error> {
     // TODO: Test if this actually maintains order in this case
     let mut sends = FuturesOrdered::new();
-    for byte in bytes {
+    for &byte in bytes {
         if byte != 0 {
             partial_message.push(byte);
             continue;
             // ends this iteration early so I don't have to indent this all the way to Saturn
         }
-        let api_version = partial_message.remove(0);
-        if api_version > crate::LATEST_API_VERSION {
-            return Err(Error::InvalidApiVersion(api_version));
+        if partial_message.is_empty() {
+            continue;
         }
 
         // safety: I am clearing this Vec after calling this
-        let message = match json::from_slice(partial_message.as_mut_slice()) {
+        let decoded = decode_message(partial_message.as_mut_slice());
+        partial_message.clear();
+
+        let message = match decoded {
             Ok(m) => m,
-            Err(e) => {
+            Err(Error::Json(e)) => {
                 warn!("Halogen server json decoding error: {e}");
                 // it isn't the end of the world, it's just one message
                 continue;
             }
+            Err(e) => return Err(e),
         };
-        partial_message.clear();
 
         sends.push_back(sender.send_async(message))
     }
@@ -338,6 +546,18 @@ async fn deserialize_bytes(
     Ok(())
 }
 
+/// Decode a single message that was formatted with [`format_message_for_sender`], without its null terminator.
+pub(crate) fn decode_message<T: serde::de::DeserializeOwned>(bytes: &mut [u8]) -> Result<T, Error> {
+    let Some((&mut api_version, message)) = bytes.split_first_mut() else {
+        return Err(Error::Internal("Received an empty message"));
+    };
+    if api_version > crate::LATEST_API_VERSION {
+        return Err(Error::InvalidApiVersion(api_version));
+    }
+
+    Ok(json::from_slice(message)?)
+}
+
 /// Messages sent to the interface for use internally
 pub enum InterfaceMessage {
     DropSocket,
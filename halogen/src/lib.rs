@@ -20,6 +20,8 @@ mod imports;
 /// The main interface
 pub mod interface;
 
+pub mod plugin;
+
 mod error;
 pub use error::Error;
 
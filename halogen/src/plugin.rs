@@ -0,0 +1,82 @@
+//! The protocol that halobar speaks with plugins, which are executables that provide data for widgets.
+//!
+//! The bar runs the plugin with its stdin and stdout piped. Both ends write the same frames as the halogen socket:
+//! the API version byte, the message as JSON, then a null byte. In Python, that is
+//! `sys.stdout.buffer.write(b"\x01" + json.dumps(message).encode() + b"\x00")`, followed by a flush.
+//!
+//! 1. The plugin sends [`PluginMessage::Hello`] with the names of the fields it provides, like
+//!    `{"Hello": {"fields": ["temperature"]}}`. It has to do this within the plugin's handshake timeout.
+//! 2. The bar sends [`BarMessage::Subscribe`] for each widget, with the fields that its format uses.
+//!    Widgets that request fields the plugin did not declare get an error instead.
+//! 3. The plugin sends [`PluginMessage::Data`] with the current value of each field, like
+//!    `{"Data": {"field": "temperature", "value": {"Float": 41.5}}}`, and again whenever a value changes.
+//!    Without a `widget`, the value is for every widget that requested the field.
+//! 4. The bar sends [`BarMessage::Event`] when the user clicks or scrolls on a widget,
+//!    and [`BarMessage::Subscribe`] or [`BarMessage::Unsubscribe`] when the config changes.
+//!
+//! The plugin can send [`PluginMessage::Error`] at any time. When the plugin exits or closes its stdout,
+//! the bar restarts it like any other module. Anything the plugin writes to its stderr is logged by the bar.
+
+use serde::de::DeserializeOwned;
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader};
+
+use crate::imports::*;
+use crate::interface::{decode_message, format_message_for_sender};
+
+/// Reads the messages that the other end of a pipe writes, like the stdout of a plugin.
+#[derive(Debug)]
+pub struct MessageReader<R> {
+    reader: BufReader<R>,
+    buffer: Vec<u8>,
+}
+impl<R: AsyncRead + Unpin> MessageReader<R> {
+    pub fn new(reader: R) -> Self {
+        Self {
+            reader: BufReader::new(reader),
+            buffer: Vec::new(),
+        }
+    }
+
+    /// Read the next message. This returns None when the pipe is closed.
+    ///
+    /// Messages that are not valid JSON for this type are skipped with a warning.
+    ///
+    /// This is cancel safe, a message that was partially read is kept until the next call.
+    pub async fn next<T: DeserializeOwned>(&mut self) -> Result<Option<T>, Error> {
+        loop {
+            if self.reader.read_until(0, &mut self.buffer).await? == 0 && self.buffer.is_empty() {
+                return Ok(None);
+            }
+
+            if self.buffer.last() == Some(&0) {
+                self.buffer.pop();
+            }
+            if self.buffer.is_empty() {
+                continue;
+            }
+
+            let decoded = decode_message(&mut self.buffer);
+            self.buffer.clear();
+
+            match decoded {
+                Ok(message) => return Ok(Some(message)),
+                Err(Error::Json(e)) => warn!("Failed to decode message from pipe: {e}"),
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
+
+/// Write a single message to a pipe, like the stdin of a plugin, and flush it.
+pub async fn write_message<W: AsyncWrite + Unpin, T: Serialize>(
+    writer: &mut W,
+    message: &T,
+) -> Result<(), Error> {
+    let Some(bytes) = format_message_for_sender(message) else {
+        return Err(Error::Internal("Failed to serialize message"));
+    };
+
+    writer.write_all(&bytes).await?;
+    writer.flush().await?;
+    Ok(())
+}
@@ -137,6 +137,10 @@ pub enum Target {
     },
     /// Messages that are sent to the client
     Client { event: Event },
+    /// Messages that control the server
+    Control { command: Command },
+    /// The server's reply to a [`Target::Control`] message
+    Reply { reply: Reply },
 }
 impl Default for Target {
     fn default() -> Self {
@@ -147,6 +151,114 @@ impl Default for Target {
     }
 }
 
+/// Commands that a client can send to control the bar
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Command {
+    /// Shut the bar down gracefully
+    Shutdown,
+    /// Ask the bar for the state of its modules. It replies with [`Reply::Introspection`].
+    Introspect,
+    /// Run the polling commands with this key again right away. It replies with [`Reply::Refreshed`].
+    Refresh { key: String },
+}
+impl Command {
+    /// Check if the bar replies to this command
+    pub fn has_reply(&self) -> bool {
+        match self {
+            Self::Shutdown => false,
+            Self::Introspect | Self::Refresh { .. } => true,
+        }
+    }
+}
+
+/// Replies that the bar sends back to the client that sent a [`Command`]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Reply {
+    /// The state of every module instance, sorted by instance name
+    Introspection { modules: Vec<ModuleInfo> },
+    /// The number of polling commands that were refreshed
+    Refreshed { count: usize },
+}
+
+/// What a module instance is doing right now
+#[derive(
+    Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, derive_more::Display,
+)]
+pub enum ModuleState {
+    /// The module was started, but it has not yielded its data yet
+    #[default]
+    #[display(fmt = "starting")]
+    Starting,
+    /// The module yielded its data and is running
+    #[display(fmt = "running")]
+    Running,
+    /// The module returned an error. It might be waiting to restart.
+    #[display(fmt = "stopped")]
+    Stopped,
+}
+
+/// The state and statistics of a single module instance
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ModuleInfo {
+    /// The name of the module instance, like `clock_utc`
+    pub instance: String,
+    /// The type of module, like `Time`
+    pub module_type: String,
+    /// The IDs of the widgets that it provides data for
+    pub widgets: Vec<String>,
+    /// The config of the module instance, as compact JSON
+    pub config: String,
+    pub state: ModuleState,
+    /// The latest data it sent, as `field: value`
+    pub last_data: Option<String>,
+    /// The latest error it sent, or that it stopped with
+    pub last_error: Option<String>,
+    /// How many times it was restarted after it stopped
+    pub restarts: u32,
+    /// How long it has been running since it last (re)started, in milliseconds
+    pub uptime_ms: Option<u64>,
+    /// The number of messages it sent since the bar started
+    pub messages: u64,
+    /// The number of messages it sent in the last minute
+    pub messages_last_minute: u64,
+}
+
+/// Messages that a plugin writes to its stdout. See [`crate::plugin`] for the protocol.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum PluginMessage {
+    /// The first message that the plugin sends, declaring the fields that widgets can request from it
+    Hello { fields: Vec<String> },
+    /// The current value of a field. Without a widget, it is for every widget that requested the field.
+    Data {
+        #[serde(default)]
+        widget: Option<String>,
+        field: String,
+        value: Variant,
+        /// The status that widgets highlight this value with
+        #[serde(default)]
+        status: Option<Status>,
+    },
+    /// The plugin failed to get data. Without a widget, it is for every widget of the plugin.
+    ///
+    /// The widgets show the error until they receive new data.
+    Error {
+        #[serde(default)]
+        widget: Option<String>,
+        message: String,
+    },
+}
+
+/// Messages that the bar writes to a plugin's stdin. See [`crate::plugin`] for the protocol.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum BarMessage {
+    /// A widget requests these fields. The plugin must send their current values for the widget, and keep them updated.
+    Subscribe { widget: String, fields: Vec<String> },
+    /// A widget does not need these fields anymore
+    Unsubscribe { widget: String, fields: Vec<String> },
+    /// The user interacted with a widget
+    Event { widget: String, event: Event },
+}
+
 /// Different events that the bar listens to that are sent to any clients that request events
 #[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub enum Event {
@@ -0,0 +1,57 @@
+//! The command-line interface of halobar
+
+use crate::prelude::*;
+use clap::Subcommand;
+
+#[derive(Debug, clap::Parser)]
+#[command(version, author, about, long_about = None)]
+pub struct Cli {
+    #[command(flatten)]
+    pub logconfig: halogen::complete::LogConfig,
+    /// Use this config file instead of `$XDG_CONFIG_HOME/halobar/config.toml`
+    #[arg(long, verbatim_doc_comment)]
+    pub config: Option<PathBuf>,
+    /// Record all the data that is sent to the frontend to this file
+    #[arg(long, verbatim_doc_comment)]
+    pub record: Option<PathBuf>,
+    /// Replay a recording instead of running the modules
+    #[arg(long, verbatim_doc_comment)]
+    pub replay: Option<PathBuf>,
+    /// How fast to replay the recording, as a multiplier. 0 replays everything at once.
+    #[arg(long, verbatim_doc_comment, default_value_t = 1.0)]
+    pub replay_speed: f64,
+    #[command(subcommand)]
+    pub frontend: Option<Frontend>,
+}
+
+/// The frontend that displays the widgets
+#[derive(Debug, Default, Clone, PartialEq, Eq, Subcommand)]
+pub enum Frontend {
+    /// Log all the widget updates. This is the default, until there is a graphical frontend.
+    #[default]
+    Log,
+    /// Print the widgets to stdout using the i3bar JSON protocol, and read click events from stdin.
+    ///
+    /// Use this as the `status_command` of swaybar or i3bar.
+    I3bar,
+    /// Run a single module instance, printing its widget as JSON lines for a waybar custom module.
+    ///
+    /// Use this as the `exec` of a custom module with `return-type` set to `json`.
+    Waybar {
+        /// The name of the module instance, like `time` or the name of a `[module.<name>]` table
+        module: InstanceName,
+        /// The format string of the widget. If this is not set, it uses the first widget in the config
+        /// that gets its data from this module.
+        #[arg(long, verbatim_doc_comment)]
+        format: Option<String>,
+    },
+}
+impl Frontend {
+    /// Determine if this frontend writes its output to stdout, which means logs have to go to stderr.
+    pub fn uses_stdout(&self) -> bool {
+        match self {
+            Self::Log => false,
+            Self::I3bar | Self::Waybar { .. } => true,
+        }
+    }
+}
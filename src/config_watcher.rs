@@ -0,0 +1,113 @@
+//! Watches the config file for changes, so the bar can reload it without restarting.
+
+use std::ffi::OsString;
+use std::os::fd::{AsFd, AsRawFd, RawFd};
+
+use nix::sys::inotify::{AddWatchFlags, InitFlags, Inotify};
+use tokio::io::unix::AsyncFd;
+
+use crate::prelude::*;
+use crate::runtime::ModuleConfig;
+
+/// Editors tend to write files in several steps, so wait for them to settle before reading.
+const SETTLE_TIME: Duration = Duration::from_millis(100);
+
+/// [`AsyncFd`] wants [`AsRawFd`], but nix's [`Inotify`] only implements [`AsFd`].
+struct InotifyFd(Inotify);
+impl AsRawFd for InotifyFd {
+    fn as_raw_fd(&self) -> RawFd {
+        self.0.as_fd().as_raw_fd()
+    }
+}
+
+/// Watches a single config file for changes.
+pub struct ConfigWatcher {
+    path: PathBuf,
+    file_name: OsString,
+    inotify: AsyncFd<InotifyFd>,
+}
+impl ConfigWatcher {
+    /// Start watching the config file at this path.
+    ///
+    /// This watches the parent directory rather than the file itself, because many editors
+    /// replace the file with a new one when saving, and it might not even exist yet.
+    pub fn new(path: PathBuf) -> R<Self> {
+        let file_name = path
+            .file_name()
+            .ok_or_eyre("Config path has no file name")?
+            .to_owned();
+        let parent = path
+            .parent()
+            .ok_or_eyre("Config path has no parent directory")?;
+
+        let inotify = Inotify::init(InitFlags::IN_NONBLOCK | InitFlags::IN_CLOEXEC)?;
+        inotify.add_watch(
+            parent,
+            AddWatchFlags::IN_CLOSE_WRITE | AddWatchFlags::IN_MOVED_TO,
+        )?;
+
+        debug!("Watching config file '{}'", path.display());
+
+        Ok(Self {
+            path,
+            file_name,
+            inotify: AsyncFd::new(InotifyFd(inotify))?,
+        })
+    }
+
+    /// Wait until the config file changes, then parse it.
+    ///
+    /// If the new config is invalid, the error is logged and this keeps waiting,
+    /// so the old config keeps running.
+    pub async fn next(&mut self) -> R<ModuleConfig> {
+        loop {
+            self.changed().await?;
+
+            // Drain whatever else the editor did while saving
+            tokio::time::sleep(SETTLE_TIME).await;
+            self.drain()?;
+
+            match halobar_config::try_from_path::<ModuleConfig>(&self.path) {
+                Ok(config) => {
+                    info!("Reloading config file '{}'", self.path.display());
+                    return Ok(config);
+                }
+                Err(e) => error!(
+                    "Error reloading config file '{}', keeping the old config: {e}",
+                    self.path.display()
+                ),
+            }
+        }
+    }
+
+    /// Wait for an event on the config file, ignoring events on other files in the directory.
+    async fn changed(&mut self) -> R<()> {
+        loop {
+            let mut guard = self.inotify.readable().await?;
+
+            let events = match guard.get_inner().0.read_events() {
+                Ok(events) => events,
+                Err(Errno::EAGAIN) => {
+                    guard.clear_ready();
+                    continue;
+                }
+                Err(e) => return Err(e.into()),
+            };
+
+            if events
+                .into_iter()
+                .any(|e| e.name.as_ref() == Some(&self.file_name))
+            {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Discard all the pending events without blocking.
+    fn drain(&self) -> R<()> {
+        match self.inotify.get_ref().0.read_events() {
+            Ok(_) | Err(Errno::EAGAIN) => Ok(()),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
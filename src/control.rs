@@ -0,0 +1,107 @@
+//! The halogen socket of the bar, which lets other programs control it.
+
+use halogen::interface::{Interface, InterfaceState, InterfaceStub, InterfaceType};
+use halogen::{Command, Message, Reply, Target};
+use tokio::net::UnixStream;
+use tokio::runtime::Runtime;
+use tokio::task::JoinHandle;
+
+use crate::introspect::Stats;
+use crate::prelude::*;
+use crate::shutdown::{Shutdown, ShutdownReason};
+
+/// The halogen server of the bar, along with the task that handles the messages it receives.
+pub struct ControlSocket {
+    stub: InterfaceStub,
+    server_handle: JoinHandle<()>,
+    listener_handle: JoinHandle<()>,
+}
+impl ControlSocket {
+    /// Start serving the halogen socket.
+    ///
+    /// If the socket exists, but nothing is listening on it, it was left behind by a bar that crashed, so it is replaced.
+    /// This returns an error if another bar is serving it.
+    pub async fn start(runtime: &Runtime, shutdown: Shutdown, stats: Stats) -> R<Self> {
+        let (mut interface, mut stub) = Interface::new(None).await?;
+
+        if interface.state() == InterfaceState::Potential(InterfaceType::Client) {
+            if UnixStream::connect(interface.path()).await.is_ok() {
+                bail!(
+                    "Another program is serving the halogen socket at '{}'",
+                    interface.path().display()
+                );
+            }
+
+            warn!(
+                "Removing stale halogen socket at '{}'",
+                interface.path().display()
+            );
+            fs::remove_file(interface.path())?;
+            (interface, stub) = Interface::new(None).await?;
+        }
+
+        info!("Serving halogen socket at '{}'", interface.path().display());
+
+        let server_handle = runtime.spawn(async move {
+            if let Err(e) = interface.server().await {
+                error!("Halogen server stopped: {e}");
+            }
+        });
+
+        let receiver = Arc::clone(&stub.receiver);
+        let sender = Arc::clone(&stub.sender);
+        let listener_handle = runtime.spawn(async move {
+            while let Ok(message) = receiver.recv_async().await {
+                if let Some(reply) = handle_message(message, &shutdown, &stats) {
+                    if sender.send(reply).is_err() {
+                        warn!("Failed to send halogen reply");
+                    }
+                }
+            }
+        });
+
+        Ok(Self {
+            stub,
+            server_handle,
+            listener_handle,
+        })
+    }
+
+    /// Stop serving and remove the socket.
+    pub fn stop(self) {
+        self.listener_handle.abort();
+        self.server_handle.abort();
+
+        match self.stub.drop_path() {
+            Ok(()) => debug!("Removed halogen socket"),
+            Err(e) => error!("Failed to remove halogen socket: {e}"),
+        }
+    }
+}
+
+/// Handle a message from a halogen client, returning the reply if it needs one.
+fn handle_message(message: Message, shutdown: &Shutdown, stats: &Stats) -> Option<Message> {
+    let Target::Control { command } = message.sender_type else {
+        trace!("Ignoring halogen message '{}'", message.identifier);
+        return None;
+    };
+
+    let reply = match command {
+        Command::Shutdown => {
+            shutdown.trigger(ShutdownReason::Requested);
+            return None;
+        }
+        Command::Introspect => Reply::Introspection {
+            modules: stats.modules(),
+        },
+        Command::Refresh { key } => Reply::Refreshed {
+            count: crate::modules::command::refresh(&key),
+        },
+    };
+
+    Some(Message {
+        sender_type: Target::Reply { reply },
+        identifier: message.identifier,
+        ..Default::default()
+    })
+}
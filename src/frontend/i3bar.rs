@@ -0,0 +1,167 @@
+//! A headless frontend that speaks the i3bar JSON protocol, for swaybar and i3bar.
+//!
+//! See `swaybar-protocol(7)` for the details.
+
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+
+use super::{WidgetState, Widgets};
+use crate::prelude::*;
+use crate::to_frontend::{FrontendChannel, FrontendSender};
+
+/// The header that starts the protocol, followed by the start of the infinite array of status lines
+const HEADER: &str = "{\"version\":1,\"click_events\":true}\n[\n";
+
+/// A single block in a status line
+#[derive(Debug, Serialize)]
+struct Block<'w> {
+    full_text: &'w str,
+    /// The name of the module instance
+    name: &'w str,
+    /// The widget's [`ModuleId`], so clicks can be sent back to it
+    instance: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    color: Option<&'static str>,
+    urgent: bool,
+}
+impl<'w> Block<'w> {
+    fn new(widget: &'w WidgetState) -> Self {
+        let status = match widget.error {
+            Some(_) => Status::Critical,
+            None => widget.status,
+        };
+
+        Self {
+            full_text: widget.display_text(),
+            name: widget.layout.instance.as_str(),
+            instance: widget.layout.id.to_string(),
+            color: status_color(status),
+            urgent: status == Status::Critical,
+        }
+    }
+}
+
+/// The text color for each status. Normal widgets use the bar's own color.
+fn status_color(status: Status) -> Option<&'static str> {
+    match status {
+        Status::Good => Some("#a6e3a1"),
+        Status::Normal => None,
+        Status::Warn => Some("#f9e2af"),
+        Status::Bad => Some("#fab387"),
+        Status::Critical => Some("#f38ba8"),
+    }
+}
+
+/// A click event that the bar sends on stdin. The other fields are ignored.
+#[derive(Debug, Deserialize)]
+struct ClickEvent {
+    instance: Option<String>,
+    button: u32,
+}
+impl ClickEvent {
+    /// Get the halogen event for this button, using the X11 button numbers.
+    fn event(&self) -> Option<Event> {
+        let event = match self.button {
+            1 => Event::Click,
+            2 => Event::MiddleClick,
+            3 => Event::RightClick,
+            4 => Event::ScrollUp,
+            5 => Event::ScrollDown,
+            _ => return None,
+        };
+        Some(event)
+    }
+}
+
+/// Run the i3bar frontend, printing a status line to stdout whenever a widget changes
+/// and forwarding the click events from stdin to the widgets' modules.
+///
+/// This should not return.
+pub async fn run(mut frontend: FrontendChannel) -> R<()> {
+    let mut stdout = tokio::io::stdout();
+    stdout.write_all(HEADER.as_bytes()).await?;
+    stdout.flush().await?;
+
+    let mut widgets = Widgets::default();
+    let mut clicks = BufReader::new(tokio::io::stdin()).lines();
+    let mut stdin_open = true;
+
+    loop {
+        select! {
+            maybe_update = frontend.data_receiver.recv() => {
+                let update = maybe_update.ok_or_eyre("Frontend data sender was dropped!")?;
+                let mut changed = widgets.apply(update);
+
+                // Print everything that arrived at once in a single status line
+                while let Ok(update) = frontend.data_receiver.try_recv() {
+                    changed |= widgets.apply(update);
+                }
+
+                if changed {
+                    write_status(&mut stdout, &widgets).await?;
+                }
+            }
+
+            line = clicks.next_line(), if stdin_open => match line? {
+                Some(line) => handle_click(&line, &widgets, &frontend.event_sender),
+                None => {
+                    debug!("stdin was closed, no more click events will be received");
+                    stdin_open = false;
+                }
+            },
+        }
+    }
+}
+
+async fn write_status(stdout: &mut tokio::io::Stdout, widgets: &Widgets) -> R<()> {
+    let blocks = widgets
+        .iter()
+        .filter(|w| w.visible)
+        .map(Block::new)
+        .collect::<Vec<_>>();
+
+    let mut line = serde_json::to_vec(&blocks)?;
+    line.extend_from_slice(b",\n");
+
+    stdout.write_all(&line).await?;
+    stdout.flush().await?;
+    Ok(())
+}
+
+/// Parse a line of the click event array, sending the event to the widget that was clicked
+fn handle_click(line: &str, widgets: &Widgets, event_sender: &FrontendSender<EventData>) {
+    // The click events are in an infinite array, so each line but the first starts with a comma.
+    let line = line.trim().trim_start_matches(',');
+    if line.is_empty() || line == "[" {
+        return;
+    }
+
+    let click = match serde_json::from_str::<ClickEvent>(line) {
+        Ok(c) => c,
+        Err(e) => {
+            warn!("Failed to parse click event '{line}': {e}");
+            return;
+        }
+    };
+
+    let Some(event) = click.event() else {
+        debug!("Ignoring click with unsupported button {}", click.button);
+        return;
+    };
+
+    let Some(widget) = click
+        .instance
+        .as_deref()
+        .and_then(|instance| widgets.iter().find(|w| w.layout.id.to_string() == instance))
+    else {
+        debug!("Received click for an unknown widget: {click:?}");
+        return;
+    };
+
+    let event_data = EventData {
+        event,
+        module: widget.layout.id.clone(),
+    };
+    if event_sender.send(event_data).is_err() {
+        warn!("Failed to send click event, the mux was dropped!");
+    }
+}
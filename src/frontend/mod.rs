@@ -0,0 +1,300 @@
+//! Frontends that display the widgets. Each one is the other end of a [`crate::to_frontend::FrontendChannel`].
+
+pub mod i3bar;
+pub mod waybar;
+
+use std::collections::HashMap;
+
+use crate::modules::{Data, ModuleType};
+use crate::prelude::*;
+use crate::to_frontend::{WidgetLayout, WidgetUpdate};
+use halobar_core::client::ProviderError;
+
+/// The current state of a single widget, rendered from the data it received.
+#[derive(Debug)]
+pub struct WidgetState {
+    pub layout: WidgetLayout,
+    segments: FmtSegmentVec,
+    error_segments: FmtSegmentVec,
+    /// The formatted text of the widget
+    pub text: String,
+    pub status: Status,
+    /// The last percentage the widget received, for frontends that can show it as a gauge
+    pub percentage: Option<u8>,
+    /// The error, if the module that provides this widget's data failed to get it
+    pub error: Option<ProviderError>,
+    /// The error format, rendered with the current error
+    error_text: String,
+    /// Whether the widget is shown, according to its visibility rules
+    pub visible: bool,
+    /// The latest value of each field that the visibility rules check
+    rule_values: AHashMap<&'static str, Data>,
+}
+impl WidgetState {
+    pub fn new(layout: WidgetLayout) -> Self {
+        // The time module formats the whole string itself.
+        // The runtime already made sure the other format strings are valid.
+        let segments = match layout.module_type {
+            ModuleType::Time => FmtSegmentVec::default(),
+            _ => FmtSegmentVec::new(&layout.format).unwrap_or_default(),
+        };
+
+        let error_segments = FmtSegmentVec::new(&layout.error_format).unwrap_or_default();
+        let visible = layout.visibility.is_visible(|_| None);
+
+        Self {
+            layout,
+            segments,
+            error_segments,
+            text: String::new(),
+            status: Status::default(),
+            percentage: None,
+            error: None,
+            error_text: String::new(),
+            visible,
+            rule_values: AHashMap::new(),
+        }
+    }
+
+    /// Get the text to show, which is the rendered error format if the widget is errored.
+    pub fn display_text(&self) -> &str {
+        match self.error {
+            Some(_) => &self.error_text,
+            None => &self.text,
+        }
+    }
+
+    /// Mark the widget as errored, rendering its error format
+    pub fn set_error(&mut self, error: ProviderError) {
+        let message = error.to_string();
+        let fields = HashMap::from([
+            ("error", Some(message.as_str())),
+            ("kind", Some(error.kind())),
+            ("instance", Some(self.layout.instance.as_str())),
+            // The text is falsy if the widget never received data
+            ("text", Some(self.text.as_str()).filter(|t| !t.is_empty())),
+        ]);
+
+        self.error_text = self.error_segments.format_map(&fields);
+        self.error = Some(error);
+    }
+
+    /// Update the widget with new data, re-rendering its text
+    pub fn update(&mut self, data: &Data) {
+        self.error = None;
+
+        if let Some(status) = data.status() {
+            self.status = status;
+        }
+        if let Some(percentage) = data.percentage() {
+            self.percentage = Some(percentage);
+        }
+
+        match data {
+            Data::Time(time) => self.text = time.0.clone(),
+            Data::Upower(upower) => {
+                // Providers send each field separately, the other variables use their cached values.
+                let (name, value) = upower.format_field();
+                let fields = HashMap::from([(name, Some(value))]);
+                self.text = self.segments.format_map(&fields);
+            }
+            Data::Plugin(plugin) => {
+                let fields = HashMap::from([(plugin.field.as_str(), Some(plugin.to_string()))]);
+                self.text = self.segments.format_map(&fields);
+            }
+            Data::Command(command) => {
+                let fields = HashMap::from([(command.field.as_str(), Some(command.to_string()))]);
+                self.text = self.segments.format_map(&fields);
+            }
+        }
+
+        let field = data.field();
+        if self.layout.visibility.uses(field) {
+            self.rule_values.insert(field, data.clone());
+            self.visible = self
+                .layout
+                .visibility
+                .is_visible(|f| self.rule_values.get(f));
+        }
+    }
+}
+
+/// All the widgets on the bar, in order.
+#[derive(Debug, Default)]
+pub struct Widgets {
+    widgets: Vec<WidgetState>,
+}
+impl Widgets {
+    /// Apply an update from the mux. This returns true if anything visible changed.
+    pub fn apply(&mut self, update: WidgetUpdate) -> bool {
+        match update {
+            WidgetUpdate::Layout(layout) => {
+                // Widgets that did not change keep their state, so they are not blank until their next update.
+                let mut old = std::mem::take(&mut self.widgets);
+
+                self.widgets = layout
+                    .into_iter()
+                    .map(|l| match old.iter().position(|w| w.layout == l) {
+                        Some(i) => old.swap_remove(i),
+                        None => WidgetState::new(l),
+                    })
+                    .collect();
+
+                true
+            }
+            WidgetUpdate::Data(data) => {
+                let Some(widget) = data.specific_target.as_ref().and_then(|t| self.get_mut(t))
+                else {
+                    debug!("Received data for a widget that is not on the bar: {data:?}");
+                    return false;
+                };
+
+                let old_text = std::mem::take(&mut widget.text);
+                let old_status = widget.status;
+                let old_percentage = widget.percentage;
+                let was_errored = widget.error.is_some();
+                let was_visible = widget.visible;
+
+                widget.update(&data.content);
+
+                was_errored
+                    || widget.visible != was_visible
+                    || widget.text != old_text
+                    || widget.status != old_status
+                    || widget.percentage != old_percentage
+            }
+            WidgetUpdate::Errored { widget, error } => match self.get_mut(&widget) {
+                Some(w) => {
+                    w.set_error(error);
+                    true
+                }
+                None => false,
+            },
+        }
+    }
+
+    /// Iterate over the widgets in order
+    #[inline]
+    pub fn iter(&self) -> std::slice::Iter<'_, WidgetState> {
+        self.widgets.iter()
+    }
+
+    fn get_mut(&mut self, id: &ModuleId) -> Option<&mut WidgetState> {
+        self.widgets.iter_mut().find(|w| &w.layout.id == id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::modules::upower::{types::DeviceType, UpowerData};
+    use crate::modules::{time::TimeData, ModuleData};
+    use crate::to_frontend::{Visibility, VisibilityRule};
+    use halobar_core::client::ErrorChain;
+
+    fn time_layout(id: ModuleId, format: &str) -> WidgetLayout {
+        WidgetLayout {
+            id,
+            instance: "time".into(),
+            module_type: ModuleType::Time,
+            format: format.to_owned(),
+            error_format: "{text} ({kind})".to_owned(),
+            visibility: Visibility::default(),
+        }
+    }
+
+    #[test]
+    fn layout_keeps_unchanged_widgets() {
+        let first = ModuleId::new("test/time").unwrap();
+        let second = ModuleId::new("test/time/1").unwrap();
+
+        let mut widgets = Widgets::default();
+        widgets.apply(WidgetUpdate::Layout(vec![time_layout(first.clone(), "%H")]));
+
+        let changed = widgets.apply(WidgetUpdate::Data(ModuleData {
+            specific_target: Some(first.clone()),
+            content: Data::Time(TimeData("12".to_owned())),
+        }));
+        assert!(changed);
+
+        widgets.apply(WidgetUpdate::Layout(vec![
+            time_layout(second, "%M"),
+            time_layout(first, "%H"),
+        ]));
+
+        let texts = widgets.iter().map(|w| w.text.as_str()).collect::<Vec<_>>();
+        assert_eq!(texts, ["", "12"]);
+    }
+
+    #[test]
+    fn errors_use_error_format() {
+        let widget = ModuleId::new("test/time").unwrap();
+        let data = WidgetUpdate::Data(ModuleData {
+            specific_target: Some(widget.clone()),
+            content: Data::Time(TimeData("12".to_owned())),
+        });
+
+        let mut widgets = Widgets::default();
+        widgets.apply(WidgetUpdate::Layout(vec![time_layout(
+            widget.clone(),
+            "%H",
+        )]));
+        widgets.apply(data.clone());
+        widgets.apply(WidgetUpdate::Errored {
+            widget,
+            error: ProviderError::Timeout {
+                source: ErrorChain::msg("No reply"),
+            },
+        });
+
+        let state = widgets.iter().next().unwrap();
+        assert_eq!(state.display_text(), "12 (timeout)");
+
+        // New data clears the error
+        widgets.apply(data);
+        assert_eq!(widgets.iter().next().unwrap().display_text(), "12");
+    }
+
+    #[test]
+    fn visibility_rules_check_fields() {
+        let widget = ModuleId::new("test/upower").unwrap();
+        let rule = |field: &str, is: &[&str]| VisibilityRule {
+            field: field.to_owned(),
+            is: is.iter().map(|v| v.to_string()).collect(),
+        };
+
+        let layout = WidgetLayout {
+            id: widget.clone(),
+            instance: "upower".into(),
+            module_type: ModuleType::Upower,
+            format: "{keyboard_brightness}".to_owned(),
+            error_format: "{error}".to_owned(),
+            visibility: Visibility {
+                show_if: vec![rule("keyboard_brightness", &[])],
+                hide_if: vec![rule("device_type", &["linepower"])],
+            },
+        };
+        let data = |content: UpowerData| {
+            WidgetUpdate::Data(ModuleData {
+                specific_target: Some(widget.clone()),
+                content: Data::Upower(content),
+            })
+        };
+        let visible = |widgets: &Widgets| widgets.iter().next().unwrap().visible;
+
+        let mut widgets = Widgets::default();
+        widgets.apply(WidgetUpdate::Layout(vec![layout]));
+        // Fields that were never received do not match
+        assert!(!visible(&widgets));
+
+        assert!(widgets.apply(data(UpowerData::KeyboardBrightness(3))));
+        assert!(visible(&widgets));
+
+        widgets.apply(data(UpowerData::DeviceType(DeviceType::LinePower)));
+        assert!(!visible(&widgets));
+
+        widgets.apply(data(UpowerData::DeviceType(DeviceType::Battery)));
+        widgets.apply(data(UpowerData::KeyboardBrightness(0)));
+        assert!(!visible(&widgets));
+    }
+}
@@ -0,0 +1,95 @@
+//! A headless frontend for waybar custom modules with `return-type` set to `json`.
+//!
+//! Waybar reads one JSON object per line from stdout, and each line replaces the previous one.
+
+use tokio::io::AsyncWriteExt;
+
+use super::{WidgetState, Widgets};
+use crate::prelude::*;
+use crate::to_frontend::FrontendChannel;
+
+/// A single line of output for waybar
+#[derive(Debug, Serialize)]
+struct Output<'w> {
+    text: &'w str,
+    tooltip: String,
+    /// The status of the widget, so it can be styled with CSS
+    class: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    percentage: Option<u8>,
+}
+impl<'w> Output<'w> {
+    fn new(widget: &'w WidgetState) -> Self {
+        // Waybar hides custom modules that have no text
+        if !widget.visible {
+            return Self {
+                text: "",
+                tooltip: String::new(),
+                class: "hidden",
+                percentage: None,
+            };
+        }
+
+        match widget.error {
+            Some(ref e) => Self {
+                text: widget.display_text(),
+                tooltip: format!("{}: {}", widget.layout.instance, e.report()),
+                class: "error",
+                percentage: widget.percentage,
+            },
+            None => Self {
+                text: widget.text.as_str(),
+                tooltip: widget.layout.instance.to_string(),
+                class: status_class(widget.status),
+                percentage: widget.percentage,
+            },
+        }
+    }
+}
+
+/// The CSS class for each status
+fn status_class(status: Status) -> &'static str {
+    match status {
+        Status::Good => "good",
+        Status::Normal => "normal",
+        Status::Warn => "warn",
+        Status::Bad => "bad",
+        Status::Critical => "critical",
+    }
+}
+
+/// Run the waybar frontend, printing a line to stdout whenever the widget changes.
+///
+/// The runtime only starts the single widget that waybar shows, so this prints the first widget it knows about.
+/// This should not return.
+pub async fn run(mut frontend: FrontendChannel) -> R<()> {
+    let mut stdout = tokio::io::stdout();
+    let mut widgets = Widgets::default();
+
+    loop {
+        let update = frontend
+            .data_receiver
+            .recv()
+            .await
+            .ok_or_eyre("Frontend data sender was dropped!")?;
+        let mut changed = widgets.apply(update);
+
+        while let Ok(update) = frontend.data_receiver.try_recv() {
+            changed |= widgets.apply(update);
+        }
+
+        if !changed {
+            continue;
+        }
+
+        let Some(widget) = widgets.iter().next() else {
+            continue;
+        };
+
+        let mut line = serde_json::to_vec(&Output::new(widget))?;
+        line.push(b'\n');
+
+        stdout.write_all(&line).await?;
+        stdout.flush().await?;
+    }
+}
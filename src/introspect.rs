@@ -0,0 +1,239 @@
+//! Keeps track of what each module instance is doing, so it can be inspected over halogen.
+
+use std::collections::VecDeque;
+use std::sync::Mutex;
+
+use halogen::{ModuleInfo, ModuleState};
+
+use crate::modules::{ModuleOutput, ModuleType};
+use crate::prelude::*;
+use halobar_core::client::ProviderError;
+
+/// How far back the message rate looks
+const RATE_WINDOW: Duration = Duration::from_secs(60);
+
+/// The statistics of a single module instance
+#[derive(Debug)]
+struct ModuleStats {
+    module_type: ModuleType,
+    config: String,
+    widgets: Vec<ModuleId>,
+    state: ModuleState,
+    /// When the module last yielded
+    started: Option<Instant>,
+    restarts: u32,
+    messages: u64,
+    /// When each message in the rate window was received
+    recent: VecDeque<Instant>,
+    last_data: Option<String>,
+    last_error: Option<String>,
+}
+impl ModuleStats {
+    fn new(module_type: ModuleType, config: String) -> Self {
+        Self {
+            module_type,
+            config,
+            widgets: Vec::new(),
+            state: ModuleState::default(),
+            started: None,
+            restarts: 0,
+            messages: 0,
+            recent: VecDeque::new(),
+            last_data: None,
+            last_error: None,
+        }
+    }
+
+    /// Forget the messages that are older than the rate window
+    fn expire(&mut self, now: Instant) {
+        while let Some(oldest) = self.recent.front() {
+            if now.duration_since(*oldest) <= RATE_WINDOW {
+                break;
+            }
+            self.recent.pop_front();
+        }
+    }
+
+    fn info(&mut self, instance: &InstanceName, now: Instant) -> ModuleInfo {
+        self.expire(now);
+
+        let uptime_ms = match self.state {
+            ModuleState::Running => self
+                .started
+                .map(|s| now.duration_since(s).as_millis() as u64),
+            _ => None,
+        };
+
+        ModuleInfo {
+            instance: instance.to_string(),
+            module_type: self.module_type.to_string(),
+            widgets: self.widgets.iter().map(|w| w.to_string()).collect(),
+            config: self.config.clone(),
+            state: self.state,
+            last_data: self.last_data.clone(),
+            last_error: self.last_error.clone(),
+            restarts: self.restarts,
+            uptime_ms,
+            messages: self.messages,
+            messages_last_minute: self.recent.len() as u64,
+        }
+    }
+}
+
+/// The statistics of every module instance. It is cheap to clone.
+///
+/// The runtime registers each instance when it spawns it, and the mux records what it yields and sends.
+#[derive(Debug, Clone, Default)]
+pub struct Stats {
+    modules: Arc<Mutex<AHashMap<InstanceName, ModuleStats>>>,
+}
+impl Stats {
+    fn with<T>(&self, f: impl FnOnce(&mut AHashMap<InstanceName, ModuleStats>) -> T) -> T {
+        let mut modules = self.modules.lock().unwrap_or_else(|e| e.into_inner());
+        f(&mut modules)
+    }
+
+    /// Start keeping track of a module instance. If it was already known, its statistics are reset.
+    pub fn register<C: Serialize>(
+        &self,
+        instance: InstanceName,
+        module_type: ModuleType,
+        config: &C,
+    ) {
+        let config = serde_json::to_value(config)
+            .map(|mut v| {
+                strip_nulls(&mut v);
+                v.to_string()
+            })
+            .unwrap_or_else(|e| format!("Failed to serialize config: {e}"));
+
+        self.with(|m| m.insert(instance, ModuleStats::new(module_type, config)));
+    }
+
+    /// Stop keeping track of a module instance that is not running anymore
+    pub fn remove(&self, instance: &InstanceName) {
+        self.with(|m| m.remove(instance));
+    }
+
+    /// Record that the module (re)started and yielded its data to these widgets
+    pub fn yielded(&self, instance: &InstanceName, widgets: &[ModuleId]) {
+        self.with(|m| {
+            if let Some(stats) = m.get_mut(instance) {
+                stats.state = ModuleState::Running;
+                stats.started = Some(Instant::now());
+                stats.widgets = widgets.to_vec();
+            }
+        });
+    }
+
+    /// Record that the widgets that the module provides data for changed, without it restarting
+    pub fn set_widgets(&self, instance: &InstanceName, widgets: &[ModuleId]) {
+        self.with(|m| {
+            if let Some(stats) = m.get_mut(instance) {
+                stats.widgets = widgets.to_vec();
+            }
+        });
+    }
+
+    /// Record that the supervisor is restarting the module
+    pub fn restarting(&self, instance: &InstanceName) {
+        self.with(|m| {
+            if let Some(stats) = m.get_mut(instance) {
+                stats.restarts += 1;
+            }
+        });
+    }
+
+    /// Record that the module stopped with an error
+    pub fn stopped(&self, instance: &InstanceName, error: &ProviderError) {
+        self.with(|m| {
+            if let Some(stats) = m.get_mut(instance) {
+                stats.state = ModuleState::Stopped;
+                stats.last_error = Some(error.report());
+            }
+        });
+    }
+
+    /// Record a message that the module sent through its subscription
+    pub fn received(&self, instance: &InstanceName, output: &ModuleOutput) {
+        let now = Instant::now();
+
+        self.with(|m| {
+            let Some(stats) = m.get_mut(instance) else {
+                return;
+            };
+
+            stats.messages += 1;
+            stats.recent.push_back(now);
+            stats.expire(now);
+
+            match output {
+                ModuleOutput::Data(data) => {
+                    stats.last_data = Some(format!("{}: {}", data.content.field(), data.content))
+                }
+                ModuleOutput::Error { error, .. } => stats.last_error = Some(error.report()),
+            }
+        });
+    }
+
+    /// Get the state of every module instance, sorted by instance name
+    pub fn modules(&self) -> Vec<ModuleInfo> {
+        let now = Instant::now();
+
+        let mut modules = self.with(|m| {
+            m.iter_mut()
+                .map(|(instance, stats)| stats.info(instance, now))
+                .collect::<Vec<_>>()
+        });
+
+        modules.sort_by(|a, b| a.instance.cmp(&b.instance));
+        modules
+    }
+}
+
+/// Remove the keys that are not set, so the config only shows what the user set
+fn strip_nulls(value: &mut serde_json::Value) {
+    if let serde_json::Value::Object(map) = value {
+        map.retain(|_, v| !v.is_null());
+        map.values_mut().for_each(strip_nulls);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::modules::{time::TimeData, Data, ModuleData};
+
+    #[test]
+    fn counts_restarts_and_messages() {
+        let stats = Stats::default();
+        let instance = InstanceName::from("clock");
+        let widget = ModuleId::new("test/clock").unwrap();
+
+        stats.register(
+            instance.clone(),
+            ModuleType::Time,
+            &crate::runtime::InstanceConfig::Time(Default::default()),
+        );
+        stats.yielded(&instance, std::slice::from_ref(&widget));
+        stats.received(
+            &instance,
+            &ModuleData {
+                specific_target: Some(widget.clone()),
+                content: Data::Time(TimeData("12:00".to_owned())),
+            }
+            .into(),
+        );
+        stats.restarting(&instance);
+        stats.yielded(&instance, std::slice::from_ref(&widget));
+
+        let [info] = stats.modules().try_into().unwrap();
+        assert_eq!(info.config, r#"{"type":"time"}"#);
+        assert_eq!(info.state, ModuleState::Running);
+        assert_eq!(info.restarts, 1);
+        assert_eq!(info.messages, 1);
+        assert_eq!(info.messages_last_minute, 1);
+        assert_eq!(info.last_data.as_deref(), Some("time: 12:00"));
+        assert_eq!(info.widgets, ["test/clock"]);
+    }
+}
@@ -3,22 +3,30 @@
 #![deny(large_assignments)]
 
 // pub mod backend;
-pub mod client;
+pub mod cli;
+pub mod config_watcher;
+pub mod control;
+pub mod frontend;
 pub mod globals;
+pub mod introspect;
 pub mod modules;
 mod prelude;
-// pub mod runtime;
+pub mod recorder;
+pub mod runtime;
+pub mod shutdown;
+pub mod supervisor;
+pub mod throttle;
 pub mod to_frontend;
 pub mod types;
 
 fn main() -> prelude::R<()> {
     color_eyre::install()?;
 
-    let mut log_config = halogen::complete::LogConfig::default();
-    log_config.level = halogen::complete::LogLevel::Debug;
+    let mut cli = <cli::Cli as clap::Parser>::parse();
+    cli.logconfig.stderr = cli.frontend.clone().unwrap_or_default().uses_stdout();
 
     const NICE_TARGETS: [&str; 5] = ["iced", "wgpu", "zbus", "zbus_xml", "cosmic_text"];
-    halogen::complete::init_log(&mut log_config, NICE_TARGETS);
+    halogen::complete::init_log(&cli.logconfig, NICE_TARGETS);
 
     let uname = nix::sys::utsname::uname()?;
     prelude::info!("Running kernel {}", uname.release().to_string_lossy());
@@ -28,10 +36,17 @@ fn main() -> prelude::R<()> {
         .build()?;
     let rt = prelude::Arc::new(rt);
 
-    // let config = runtime::ModuleConfig::default();
+    let config_path = cli.config.clone().or_else(runtime::config_path);
+    let config =
+        halobar_config::from_path_or_default::<runtime::ModuleConfig>(config_path.as_deref())
+            .unwrap_or_else(|default| default);
 
-    // rt.clone().block_on(modules::run(rt, config))?;
-    // rt.clone().block_on(runtime::run(rt.clone(), config))?;
+    // Dropping the runtime would wait for blocking reads from stdin, which never finish while the bar is open,
+    // so it has to outlive the call to exit.
+    let reason = rt.block_on(runtime::run(rt.clone(), config, config_path, cli))?;
 
-    Ok(())
+    prelude::info!("Exiting, {reason}");
+    // Statics are not dropped on exit, so the logfile has to be flushed by hand
+    halogen::complete::flush_log();
+    std::process::exit(reason.exit_code())
 }
@@ -1,114 +1,76 @@
 pub mod command;
+pub mod plugin;
 pub mod time;
 pub mod upower;
 
-use crate::{
-    client::{DataRequest, ProviderError, Request},
-    prelude::*,
+pub use halobar_core::modules::{
+    Data, ModuleData, ModuleDataProvider, ModuleMessage, ModuleOutput, ModuleType, ModuleYield,
+    RequestField,
 };
 
-/// A module that can be used in the backend to provide data.
-pub trait ModuleDataProvider: Sized + Send {
-    /// The type of input that the module requires to create a new instance,
-    /// including any type of config that the module requires for user customization.
-    ///
-    /// This must not have any required fields!!!
-    ///
-    /// This input is cloned before the module is created, so that duplicate modules/bars are not broken.
-    /// The module is responsible for ensuring this is not too expensive.
-    type ServerConfig: Clone;
+use crate::prelude::*;
+use halobar_core::client::{DataRequest, ProviderError, Request};
+use std::process::Stdio;
+use tokio::{
+    io::{AsyncBufReadExt, BufReader},
+    process::{ChildStderr, Command},
+};
 
-    /// This is the entry point for the data provider. This initializes it with its config,
-    /// its interface to the outside world, and a buffer that tells it what to watch for.
-    ///
-    /// It takes ownership of the data requests, fulfills them (or provides errors if it can't fulfull them),
-    /// and then passes the request vector back out with initial data values so the frontend can initialize whatever asked for data.
-    async fn main(
-        config: Self::ServerConfig,
-        requests: Vec<DataRequest>,
-        yield_channel: mpsc::UnboundedSender<ModuleYield>,
-    ) -> R<()>;
+/// Determine what fields a widget of this type of module needs for its format
+pub fn request_fields(module_type: &ModuleType, format: &str) -> R<Vec<RequestField>> {
+    match module_type {
+        ModuleType::Time => time::Time::request_fields(format),
+        ModuleType::Upower => upower::UpowerMod::request_fields(format),
+        ModuleType::Plugin => plugin::PluginModule::request_fields(format),
+        ModuleType::Command => command::CommandModule::request_fields(format),
+    }
 }
 
-/// All the data that is yielded from each data provider.
-///
-/// It will provide a channel for events to be sent and data to be received if it
-/// is a dynamic module.
-///
-/// Additionally, it will give the initial data request vector back to the frontend.
-///
-/// This is required to tie it to the frontend.
-pub struct ModuleYield {
-    pub subscription: Option<BiChannel<Event, ModuleData>>,
-    pub fulfilled_requests: Vec<DataRequest>,
+/// Determine what fields a widget of this type of module needs for its visibility rules
+pub fn rule_fields(module_type: &ModuleType, fields: &[&str]) -> R<Vec<RequestField>> {
+    match module_type {
+        ModuleType::Time => time::Time::rule_fields(fields),
+        ModuleType::Upower => upower::UpowerMod::rule_fields(fields),
+        ModuleType::Plugin => plugin::PluginModule::rule_fields(fields),
+        ModuleType::Command => command::CommandModule::rule_fields(fields),
+    }
 }
 
-// #[derive(Debug, Clone, PartialEq, Eq)]
-// pub enum Data {
-//     Time(time::TimeData),
-// }
-
-/// Content that can be sent to the frontend.
+/// Request a field for each variable in this format, once each, without checking their names.
 ///
-/// TODO: Finalize stuff required.
-#[derive(Debug, Clone, PartialEq)]
-pub struct ModuleData {
-    pub specific_target: Option<ModuleId>,
-    pub content: Data,
-    // pub module_type: ModuleType,
-}
-impl ModuleData {
-    /// Create module data
-    #[inline]
-    pub const fn new(content: Data) -> Self {
-        Self {
-            specific_target: None,
-            content,
-        }
-    }
-}
+/// This is for the modules whose fields are only known once they start, which check them then.
+fn request_variables(format: &str, field: impl Fn(&str) -> RequestField) -> R<Vec<RequestField>> {
+    let segments = FmtSegmentVec::new(format)?;
 
-macro_rules! data_enum {
-    ($( [$module:ident] data_type: $( $data_type:ty ),+; request_field: $req_field_type:ty );+$(;)?) => {
-        /// The type of module. Should be tiny and contain nothing
-        #[derive(
-            Debug,
-            Clone,
-            PartialEq,
-            Eq,
-            PartialOrd,
-            Ord,
-            Hash,
-            Serialize,
-            Deserialize,
-            strum_macros::Display,
-            strum_macros::AsRefStr,
-            strum_macros::EnumString,
-        )]
-        pub enum ModuleType {
-            $( $module ),+
-        }
+    let mut fields = Vec::new();
+    for segment in segments.segments() {
+        let halobar_config::fmt::Segment::Variable(var) = segment else {
+            continue;
+        };
 
-        /// The data a module can provide. This is an enum, with a branch for each
-        /// data provider, and an inner tuple where the data is carried.
-        #[derive(Debug, Clone, PartialEq, derive_more::From)]
-        pub enum Data {
-            $( $module($( $data_type ),+) ),+
+        let field = field(&var.ident);
+        if !fields.contains(&field) {
+            fields.push(field);
         }
+    }
 
-        /// These are the fields that you can request. This is sent to the providers.
-        #[derive(Debug, Clone, PartialEq, derive_more::From)]
-        pub enum RequestField {
-            $( $module($req_field_type) ),+
-        }
-    };
+    Ok(fields)
+}
+
+/// Pipe the output of a child process that a module runs.
+fn pipe_output(command: &mut Command) {
+    command.stdout(Stdio::piped());
+    command.stderr(Stdio::piped());
+    // The module is aborted when the bar shuts down or the config changes, so the child has to die with it.
+    command.kill_on_drop(true);
 }
 
-data_enum! {
-    [Time]
-    data_type: time::TimeData;
-    request_field: String;
-    [Upower]
-    data_type: upower::UpowerData;
-    request_field: upower::UpowerDataDiscriminants;
+/// Log each line that a child process prints to stderr as a warning, prefixed with `name`, until it exits.
+fn spawn_stderr_logger(name: String, stderr: ChildStderr) {
+    tokio::spawn(async move {
+        let mut lines = BufReader::new(stderr).lines();
+        while let Ok(Some(line)) = lines.next_line().await {
+            warn!("{name}: {line}");
+        }
+    });
 }
@@ -1,23 +1,115 @@
-use std::process::Stdio;
+use std::num::NonZeroU64;
+use std::process::{ExitStatus, Stdio};
+use std::sync::{Mutex, Weak};
+
+use halogen::Variant;
+use once_cell::sync::Lazy;
 
 use super::*;
+use crate::supervisor::{Backoff, RestartKnown};
+use halobar_core::client::ErrorChain;
+pub use halobar_core::modules::command::{CommandData, CommandField};
 
 const DEFAULT_SHELL: &str = "/bin/sh";
 
-use smallvec::SmallVec;
+/// How long the module waits for the first line of output before it shows its widgets empty
+const INITIAL_OUTPUT_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// The exit status of a command that timed out, since it is killed with SIGKILL
+const TIMED_OUT_STATUS: i32 = 128 + nix::libc::SIGKILL;
+
 use tokio::{
-    io::AsyncReadExt,
-    process::{Child, Command},
+    io::{AsyncBufReadExt, BufReader},
+    process::Command,
+    signal::unix::{Signal, SignalKind},
+    sync::Notify,
 };
 
+/// The refresh notifiers of the polling commands that are running, by key, so they can be refreshed over halogen.
+///
+/// A notifier is dropped when its module stops, so the ones that can't be upgraded are removed as they are found.
+static REFRESHERS: Lazy<Mutex<AHashMap<String, Vec<Weak<Notify>>>>> =
+    Lazy::new(|| Mutex::new(AHashMap::new()));
+
+/// Run the polling commands with this key again right away, returning how many there were.
+pub fn refresh(key: &str) -> usize {
+    let mut refreshers = REFRESHERS.lock().unwrap_or_else(|e| e.into_inner());
+
+    let Some(notifiers) = refreshers.get_mut(key) else {
+        return 0;
+    };
+    notifiers.retain(|n| n.strong_count() > 0);
+
+    for notifier in notifiers.iter().filter_map(Weak::upgrade) {
+        notifier.notify_one();
+    }
+    notifiers.len()
+}
+
+/// Make a polling command refreshable over halogen
+fn register_refresher(key: &str, refresh: &Arc<Notify>) {
+    let mut refreshers = REFRESHERS.lock().unwrap_or_else(|e| e.into_inner());
+
+    let notifiers = refreshers.entry(key.to_owned()).or_default();
+    notifiers.retain(|n| n.strong_count() > 0);
+    notifiers.push(Arc::downgrade(refresh));
+}
+
+/// Listen for the real-time signal `SIGRTMIN+offset`
+fn realtime_signal(offset: u8) -> R<Signal> {
+    let signal = nix::libc::SIGRTMIN() + i32::from(offset);
+    if signal > nix::libc::SIGRTMAX() {
+        bail!("SIGRTMIN+{offset} is higher than SIGRTMAX");
+    }
+
+    Ok(tokio::signal::unix::signal(SignalKind::from_raw(signal))?)
+}
+
 config_struct! {
-    @config {Clone}
+    @known {Clone}
+    @config {Clone, PartialEq}
     [Command]
+    // The name of the command in the logs. This defaults to the command itself.
     key: String = String::new(),
+    @conf @config #[serde(flatten)]
+    @conf throttle: crate::throttle => Throttle,
     command: String = "date".into(),
+    // The shell that runs the command, with `-c`. This defaults to /bin/sh.
     shell: String = String::new(),
     env: AHashMap<String, String> = AHashMap::new(),
     output_type: OutputTypeConfig = OutputTypeConfig::default(),
+    output_format: OutputFormat = OutputFormat::default(),
+    // Kill a static or polling command that runs for longer than this. 0 disables it.
+    timeout_ms: u64 = 0,
+    // How a watcher is restarted when it exits. A watcher that exits successfully is only restarted with `policy = "Always"`.
+    @conf @config #[serde(default)]
+    @conf restart: crate::supervisor => Restart,
+    // Commands that run when the widget is clicked or scrolled, with the same shell and env
+    on_click: String = String::new(),
+    on_right_click: String = String::new(),
+    on_middle_click: String = String::new(),
+    on_scroll_up: String = String::new(),
+    on_scroll_down: String = String::new(),
+    // Run a polling command again as soon as the command of a click or scroll finishes
+    refresh_on_event: bool = false,
+    // Run a polling command again when the bar receives SIGRTMIN+signal, like `pkill -RTMIN+1 halobar`. 0 disables it.
+    signal: u8 = 0,
+}
+impl CommandKnown {
+    /// Get the commands that are bound to each event
+    fn bindings(&self) -> Vec<(Event, String)> {
+        [
+            (Event::Click, &self.on_click),
+            (Event::RightClick, &self.on_right_click),
+            (Event::MiddleClick, &self.on_middle_click),
+            (Event::ScrollUp, &self.on_scroll_up),
+            (Event::ScrollDown, &self.on_scroll_down),
+        ]
+        .into_iter()
+        .filter(|(_, command)| !command.is_empty())
+        .map(|(event, command)| (event, command.clone()))
+        .collect()
+    }
 }
 
 struct CommandBuilder {
@@ -26,134 +118,698 @@ struct CommandBuilder {
     pub command: String,
     pub shell: Option<String>,
     pub env: Vec<(String, String)>,
+    /// The commands that run when the widget is clicked or scrolled
+    pub bindings: Vec<(Event, String)>,
+    pub refresh_on_event: bool,
+    /// How long a static or polling command can run before it is killed
+    pub timeout: Option<Duration>,
+    pub restart: RestartKnown,
+}
+impl From<&CommandKnown> for CommandBuilder {
+    fn from(config: &CommandKnown) -> Self {
+        let key = match config.key.is_empty() {
+            true => config.command.clone(),
+            false => config.key.clone(),
+        };
+
+        Self {
+            output_type: config.output_type.into(),
+            key,
+            command: config.command.clone(),
+            shell: (!config.shell.is_empty()).then(|| config.shell.clone()),
+            env: config
+                .env
+                .iter()
+                .map(|(k, v)| (k.clone(), v.clone()))
+                .collect(),
+            bindings: config.bindings(),
+            refresh_on_event: config.refresh_on_event,
+            timeout: (config.timeout_ms != 0).then(|| Duration::from_millis(config.timeout_ms)),
+            restart: config.restart,
+        }
+    }
 }
 impl CommandBuilder {
-    /// Create a [`Comm`] struct out of this builder
-    pub async fn run(&mut self, sender: Arc<mpsc::UnboundedSender<String>>) -> CommandResult<()> {
+    /// Create a command that runs this command line in the shell, with the env
+    fn shell_command(&self, command_line: &str) -> Command {
         let mut command = Command::new(self.shell.as_deref().unwrap_or(DEFAULT_SHELL));
 
-        command.args(["-c", self.command.as_str()]);
+        command.args(["-c", command_line]);
         command.envs(self.env.iter().map(|s| (s.0.as_str(), s.1.as_str())));
+        command
+    }
+
+    /// Create the command that the module runs, with its output piped
+    fn output_command(&self) -> Command {
+        let mut command = self.shell_command(&self.command);
 
-        command.stdout(Stdio::piped());
         command.stdin(Stdio::null());
+        pipe_output(&mut command);
+        command
+    }
 
+    /// Run the command, sending each line that it prints and its exit status through the sender.
+    ///
+    /// A polling command runs again right away when `refresh` is notified.
+    /// This returns when a static command exits, or when a watcher exits and is not restarted. Polling commands run until they fail.
+    pub async fn run(
+        &self,
+        sender: Arc<mpsc::UnboundedSender<CommandOutput>>,
+        refresh: &Notify,
+    ) -> CommandResult<()> {
         match self.output_type {
-            OutputType::Static => {
-                let output = command.output().await?;
+            OutputType::Static => self.run_once(&sender).await,
+            OutputType::Poll(duration) => loop {
+                // The interval starts with the command, so slow commands don't drift.
+                let next_run = tokio::time::Instant::now() + duration;
 
-                let stdoutput = String::from_utf8_lossy(&output.stdout);
+                self.run_once(&sender).await?;
 
-                for line in stdoutput.lines() {
-                    sender.send(line.to_owned())?;
-                }
-            }
-            OutputType::Poll(duration) => loop {
-                let (output, _) = try_join!(command.output(), async {
-                    // I make a new future like this because try_join expects both futures to return Result
-                    tokio::time::sleep(duration).await;
-                    Ok(())
-                })?;
-                let stdoutput = String::from_utf8_lossy(&output.stdout);
-
-                for line in stdoutput.lines() {
-                    sender.send(line.to_owned())?;
+                select! {
+                    () = tokio::time::sleep_until(next_run) => {}
+                    () = refresh.notified() => debug!("Refreshing command {}", self.key),
                 }
             },
-            OutputType::Watcher => {
-                /// This is the newline character (\n)
-                const NEWLINE: u8 = 10;
-                // TODO: Remove
-                debug_assert_eq!(NEWLINE, *b"\n".first().unwrap());
-
-                let mut child = command.spawn()?;
-
-                if let Some(pid) = child.id() {
-                    debug!(
-                        "Spawned command {} with pid {pid}: {}",
-                        &self.key, &self.command
+            OutputType::Watcher => self.watch(&sender).await,
+        }
+    }
+
+    /// Run a static or polling command until it exits, killing it if it times out.
+    ///
+    /// Its output is only read once it exits, so a command that times out only sends its exit status.
+    async fn run_once(&self, sender: &mpsc::UnboundedSender<CommandOutput>) -> CommandResult<()> {
+        let output = self.output_command().output();
+
+        let output = match self.timeout {
+            // The child is killed when its output is dropped.
+            Some(timeout) => match tokio::time::timeout(timeout, output).await {
+                Ok(output) => output?,
+                Err(_) => {
+                    warn!(
+                        "Command {} timed out after {}ms",
+                        self.key,
+                        timeout.as_millis()
                     );
+                    sender.send(CommandOutput::Exited(TIMED_OUT_STATUS))?;
+                    return Ok(());
                 }
+            },
+            None => output.await?,
+        };
 
-                let mut child_stdout =
-                    child
-                        .stdout
-                        .take()
-                        .ok_or_else(|| match child.start_kill() {
-                            Ok(()) => CommandError::MissingStdout,
-                            Err(e) => e.into(),
-                        })?;
+        for line in String::from_utf8_lossy(&output.stderr).lines() {
+            warn!("Command {}: {line}", self.key);
+        }
+        for line in String::from_utf8_lossy(&output.stdout).lines() {
+            sender.send(CommandOutput::Line(line.to_owned()))?;
+        }
+        sender.send(CommandOutput::Exited(exit_code(output.status)))?;
 
-                let mut current_line = Vec::new();
-                let mut buffer = [0u8; 256];
-
-                loop {
-                    trace!("Reading output from command {}", &self.key);
-
-                    let read_bytes = child_stdout.read(&mut buffer).await?;
-
-                    if read_bytes == 0 {
-                        // in this case, the child process is probably dead, since tokio just awaits otherwise.
-                        // I will just make sure
-                        match child.try_wait()? {
-                            Some(status) => {
-                                error!("Command {} exited with status {}", self.key, status);
-                                break;
-                            }
-                            None => {
-                                // No bytes were read, this should not occur!
-                                error!("Command {} exited without notifying watcher!", self.key);
-                                break;
-                            }
+        Ok(())
+    }
+
+    /// Run a watcher, restarting it with a backoff when it exits, according to the restart config.
+    ///
+    /// The module keeps showing the last output of a watcher that is not restarted anymore.
+    async fn watch(&self, sender: &mpsc::UnboundedSender<CommandOutput>) -> CommandResult<()> {
+        let mut backoff = Backoff::new(&self.restart);
+
+        loop {
+            let started = Instant::now();
+
+            let result = match self.watch_once(sender).await {
+                Ok(status) => {
+                    sender.send(CommandOutput::Exited(exit_code(status)))?;
+                    match status.success() {
+                        true => {
+                            debug!("Command {} exited", self.key);
+                            Ok(())
                         }
+                        false => Err(eyre!("Command {} exited with {status}", self.key)),
                     }
+                }
+                // The module is gone, so there is nothing to restart it for.
+                Err(CommandError::SendError) => return Err(CommandError::SendError),
+                Err(e) => Err(eyre!("Failed to run command {}: {e}", self.key)),
+            };
 
-                    let mut buffer_iter = buffer.iter_mut();
-
-                    while let Some(byte) = buffer_iter.next() {
-                        // if it is a nullbyte, we are probably waiting on some more bytes to be read
-                        match *byte {
-                            0 => break,
-                            NEWLINE => {
-                                let line_string = String::from_utf8_lossy(&current_line);
-                                sender.send(line_string.into())?;
-
-                                current_line.clear();
-                            }
-                            _ => {
-                                current_line.push(*byte);
-                            }
-                        }
+            if let Err(ref e) = result {
+                warn!("{e}");
+            }
+
+            if !self.restart.policy.should_restart(&result) {
+                return Ok(());
+            }
+
+            if backoff.is_exhausted() {
+                error!(
+                    "Command {} reached its maximum of {} restarts, giving up",
+                    self.key,
+                    backoff.restarts()
+                );
+                return Ok(());
+            }
+
+            let delay = backoff.next_delay(started.elapsed());
+            warn!("Restarting command {} in {}ms", self.key, delay.as_millis());
+            tokio::time::sleep(delay).await;
+        }
+    }
+
+    /// Run a watcher once, sending each line that it prints until it exits
+    async fn watch_once(
+        &self,
+        sender: &mpsc::UnboundedSender<CommandOutput>,
+    ) -> CommandResult<ExitStatus> {
+        let mut child = self.output_command().spawn()?;
+
+        if let Some(pid) = child.id() {
+            debug!(
+                "Spawned command {} with pid {pid}: {}",
+                &self.key, &self.command
+            );
+        }
+
+        let stdout = child.stdout.take().ok_or(CommandError::MissingStdout)?;
+
+        if let Some(stderr) = child.stderr.take() {
+            spawn_stderr_logger(format!("Command {}", self.key), stderr);
+        }
+
+        let mut stdout = BufReader::new(stdout);
+        let mut line = Vec::new();
+        loop {
+            trace!("Reading output from command {}", &self.key);
+
+            line.clear();
+            if stdout.read_until(b'\n', &mut line).await? == 0 {
+                break;
+            }
+
+            // The last line might not end with a newline.
+            let line = line.strip_suffix(b"\n").unwrap_or(&line);
+            sender.send(CommandOutput::Line(
+                String::from_utf8_lossy(line).into_owned(),
+            ))?;
+        }
+
+        // The command closed its stdout, which it usually only does when it exits.
+        Ok(child.wait().await?)
+    }
+
+    /// Run the command that is bound to this event in the background, if there is one.
+    ///
+    /// If `refresh_on_event` is set, a polling command is refreshed when it finishes.
+    pub fn handle_event(&self, event: Event, refresh: Arc<Notify>) {
+        let Some((_, command_line)) = self.bindings.iter().find(|(e, _)| *e == event) else {
+            trace!("Command {} has no command for event {event:?}", self.key);
+            return;
+        };
+
+        let mut command = self.shell_command(command_line);
+        // The command might open a window, so it is not killed with the module.
+        command.stdin(Stdio::null());
+        command.stdout(Stdio::null());
+        command.stderr(Stdio::piped());
 
-                        // it is safe to reuse the same buffer because I am clearing all the data after reading it.
-                        *byte = 0;
+        let key = self.key.clone();
+        let refresh_poll = self.refresh_on_event && matches!(self.output_type, OutputType::Poll(_));
+
+        tokio::spawn(async move {
+            debug!("Running {event:?} command of {key}");
+            let status = match command.spawn() {
+                Ok(mut child) => {
+                    if let Some(stderr) = child.stderr.take() {
+                        spawn_stderr_logger(format!("Command {key}"), stderr);
                     }
+                    child.wait().await
+                }
+                Err(e) => Err(e),
+            };
+
+            match status {
+                Ok(status) if !status.success() => {
+                    warn!("{event:?} command of {key} exited with {status}")
                 }
+                Ok(_) => {}
+                Err(e) => error!("Failed to run {event:?} command of {key}: {e}"),
             }
+
+            if refresh_poll {
+                refresh.notify_one();
+            }
+        });
+    }
+}
+
+/// What the command sends to the module while it runs
+#[derive(Debug)]
+enum CommandOutput {
+    /// A line that the command printed
+    Line(String),
+    /// The command exited with this exit status
+    Exited(i32),
+}
+
+/// Get the exit status like shells show it in `$?`, where a command that was killed by a signal exits with 128 + the signal.
+fn exit_code(status: ExitStatus) -> i32 {
+    use std::os::unix::process::ExitStatusExt;
+
+    status
+        .code()
+        .unwrap_or_else(|| 128 + status.signal().unwrap_or_default())
+}
+
+/// How the module reads each line that the command prints, like `output_format = "json"`
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OutputFormat {
+    /// The whole line is the `output` variable
+    #[default]
+    Text,
+    /// Each line is a JSON object, like `{"text": "50%", "status": "warn"}`, and each of its keys is a variable.
+    ///
+    /// `status` sets the status of the widget, and `percentage` is shown as a gauge by frontends that support it.
+    Json,
+}
+impl OutputFormat {
+    /// Check if the command provides this variable with this format
+    fn provides(self, field: CommandField) -> bool {
+        match self {
+            Self::Text => field == CommandField::OUTPUT || field == CommandField::EXIT_STATUS,
+            // The keys of JSON objects are only known once the command prints them.
+            Self::Json => true,
         }
+    }
+}
 
-        Ok(())
+/// A line that the command printed, parsed according to the output format
+#[derive(Debug, Default)]
+struct Line {
+    /// The values by name. The names come from the command, so they are only looked up when a widget uses them.
+    values: AHashMap<String, Variant>,
+    status: Option<Status>,
+}
+impl Line {
+    fn parse(format: OutputFormat, line: String) -> Result<Self, ProviderError> {
+        match format {
+            OutputFormat::Text => Ok(Self {
+                values: AHashMap::from_iter([(
+                    CommandField::OUTPUT.as_str().to_owned(),
+                    Variant::String(line),
+                )]),
+                status: None,
+            }),
+            OutputFormat::Json => {
+                let object =
+                    serde_json::from_str::<serde_json::Map<String, serde_json::Value>>(&line)
+                        .map_err(|e| ProviderError::Parse {
+                            what: "the JSON output of the command".to_owned(),
+                            source: ErrorChain::new(&e),
+                        })?;
+
+                let status = match object.get("status") {
+                    Some(serde_json::Value::String(s)) => parse_status(s),
+                    Some(other) => {
+                        warn!("Ignoring command status that is not a string: {other}");
+                        Status::default()
+                    }
+                    None => Status::default(),
+                };
+
+                let values = object
+                    .into_iter()
+                    .filter_map(|(key, value)| Some((key, json_variant(value)?)))
+                    .collect();
+
+                // Every line sets the status, so a line without one clears the status of the line before it.
+                Ok(Self {
+                    values,
+                    status: Some(status),
+                })
+            }
+        }
+    }
+
+    /// Get the value of a variable. A variable that the line does not have is empty, so it is falsy.
+    fn data(&self, field: CommandField) -> CommandData {
+        CommandData {
+            field,
+            value: self
+                .values
+                .get(field.as_str())
+                .cloned()
+                .unwrap_or_else(|| Variant::String(String::new())),
+            status: self.status,
+        }
+    }
+}
+
+/// Get the status that a JSON line sets, accepting the names that i3blocks and waybar scripts use
+fn parse_status(status: &str) -> Status {
+    match status.to_ascii_lowercase().as_str() {
+        "good" | "ok" => Status::Good,
+        "" | "normal" | "idle" | "info" => Status::Normal,
+        "warn" | "warning" => Status::Warn,
+        "bad" | "error" => Status::Bad,
+        "critical" | "urgent" => Status::Critical,
+        other => {
+            warn!("Unknown command status: {other}");
+            Status::default()
+        }
     }
 }
 
+/// Convert a JSON value to a variant. Nulls are left out, like keys that are not set.
+fn json_variant(value: serde_json::Value) -> Option<Variant> {
+    use serde_json::Value;
+
+    let variant = match value {
+        Value::Null => return None,
+        Value::Bool(b) => Variant::Bool(b),
+        Value::Number(n) => match (n.as_u64(), n.as_i64()) {
+            (Some(u), _) => Variant::Uint(u),
+            (None, Some(i)) => Variant::Iint(i),
+            (None, None) => Variant::Float(n.as_f64().unwrap_or_default()),
+        },
+        Value::String(s) => Variant::String(s),
+        Value::Array(items) => Variant::Vec(
+            items
+                .into_iter()
+                .filter_map(json_variant)
+                .map(Box::new)
+                .collect(),
+        ),
+        Value::Object(map) => Variant::Map(
+            map.into_iter()
+                .filter_map(|(key, value)| Some((key, Box::new(json_variant(value)?))))
+                .collect(),
+        ),
+    };
+    Some(variant)
+}
+
+/// The output of the command that the widgets are subscribed to
+struct CommandState {
+    output_format: OutputFormat,
+    /// The last line that the command printed, which new subscribers get right away
+    last_line: Result<Line, ProviderError>,
+    /// The exit status of the last run of the command, once it exited
+    exit_status: Option<i32>,
+    subscribers: FanOut<CommandField>,
+    channel: BiChannel<ModuleOutput, ModuleMessage>,
+    builder: Arc<CommandBuilder>,
+    /// Notified to run a polling command again right away
+    refresh: Arc<Notify>,
+}
+impl CommandState {
+    /// Get the current value of a variable
+    fn value(&self, field: CommandField) -> Result<CommandData, ProviderError> {
+        if !self.output_format.provides(field) {
+            return Err(ProviderError::InvalidField(RequestField::Command(field)));
+        }
+
+        // This is the same for every output format, and does not depend on the output.
+        if field == CommandField::EXIT_STATUS {
+            return Ok(CommandData {
+                field,
+                value: match self.exit_status {
+                    Some(status) => Variant::Iint(status.into()),
+                    None => Variant::String(String::new()),
+                },
+                status: None,
+            });
+        }
+
+        match self.last_line {
+            Ok(ref line) => Ok(line.data(field)),
+            Err(ref e) => Err(e.clone()),
+        }
+    }
+
+    /// Apply output of the command, returning the variable that changed, or `None` if a line changed all the others.
+    fn apply(&mut self, output: CommandOutput) -> Option<CommandField> {
+        match output {
+            CommandOutput::Line(line) => {
+                self.last_line = Line::parse(self.output_format, line);
+                None
+            }
+            CommandOutput::Exited(status) => {
+                self.exit_status = Some(status);
+                Some(CommandField::EXIT_STATUS)
+            }
+        }
+    }
+
+    /// Apply output of the command, and send what changed to every widget that shows it
+    async fn update(&mut self, output: CommandOutput, key: &str) {
+        let changed = self.apply(output);
+
+        let mut outputs = Vec::new();
+        match (changed, &self.last_line) {
+            (Some(field), _) => {
+                if let Ok(data) = self.value(field) {
+                    outputs.extend(
+                        self.subscribers
+                            .fan_out(&field, &Data::Command(data))
+                            .into_iter()
+                            .map(ModuleOutput::Data),
+                    );
+                }
+            }
+            (None, Ok(line)) => {
+                for (field, _) in self.subscribers.iter() {
+                    if *field == CommandField::EXIT_STATUS {
+                        continue;
+                    }
+
+                    let content = Data::Command(line.data(*field));
+                    outputs.extend(
+                        self.subscribers
+                            .fan_out(field, &content)
+                            .into_iter()
+                            .map(ModuleOutput::Data),
+                    );
+                }
+            }
+            (None, Err(error)) => {
+                warn!(
+                    "Failed to read the output of command {key}: {}",
+                    error.report()
+                );
+
+                // Each widget only needs to show the error once, no matter how many variables it shows
+                let mut widgets = Vec::new();
+                for widget in self
+                    .subscribers
+                    .iter()
+                    .filter(|(field, _)| **field != CommandField::EXIT_STATUS)
+                    .flat_map(|(_, w)| w)
+                {
+                    if !widgets.contains(widget) {
+                        widgets.push(widget.clone());
+                    }
+                }
+
+                outputs.extend(widgets.into_iter().map(|widget| ModuleOutput::Error {
+                    widget: Some(widget),
+                    error: error.clone(),
+                }));
+            }
+        }
+
+        for output in outputs {
+            self.channel.send(output).await;
+        }
+    }
+
+    /// Handle a message from the mux while running
+    async fn handle_message(&mut self, message: ModuleMessage) {
+        match message {
+            ModuleMessage::Event { event, .. } => {
+                self.builder.handle_event(event, Arc::clone(&self.refresh))
+            }
+            ModuleMessage::Subscribe(request) => {
+                for field in request.data_fields {
+                    let Request::Request(RequestField::Command(command_field)) = field else {
+                        warn!("Command module received invalid subscription: {field:?}");
+                        let error = ModuleOutput::Error {
+                            widget: Some(request.id.clone()),
+                            error: ProviderError::InvalidRequest(Box::new(field)),
+                        };
+                        self.channel.send(error).await;
+                        continue;
+                    };
+
+                    let output = match self.value(command_field) {
+                        Ok(data) => ModuleOutput::Data(ModuleData {
+                            specific_target: Some(request.id.clone()),
+                            content: Data::Command(data),
+                        }),
+                        Err(error) => ModuleOutput::Error {
+                            widget: Some(request.id.clone()),
+                            error,
+                        },
+                    };
+
+                    if self.output_format.provides(command_field) {
+                        self.subscribers
+                            .subscribe(command_field, request.id.clone());
+                    }
+                    self.channel.send(output).await;
+                }
+            }
+            ModuleMessage::Unsubscribe { widget, fields } => {
+                for field in fields {
+                    if let RequestField::Command(command_field) = field {
+                        self.subscribers.unsubscribe(&command_field, &widget);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// A module that runs a shell command, and shows the lines that it prints
 pub struct CommandModule;
 impl ModuleDataProvider for CommandModule {
     type ServerConfig = CommandConfig;
+    fn request_fields(format: &str) -> R<Vec<RequestField>> {
+        // The variables depend on the output format, so they are checked when the module starts.
+        request_variables(format, |var| RequestField::Command(CommandField::new(var)))
+    }
     async fn main(
         config: Self::ServerConfig,
-        requests: Vec<DataRequest>,
+        mut requests: Vec<DataRequest>,
         yield_channel: mpsc::UnboundedSender<ModuleYield>,
     ) -> R<()> {
-        todo!();
+        let config = config.into_known();
+        let builder = Arc::new(CommandBuilder::from(&config));
+        let refresh = Arc::new(Notify::new());
+        let key = builder.key.clone();
+
+        // Polling commands can be refreshed by key over halogen, or with their signal.
+        let mut signal = None;
+        match builder.output_type {
+            OutputType::Poll(_) => {
+                register_refresher(&key, &refresh);
+                if config.signal != 0 {
+                    signal = Some(realtime_signal(config.signal)?);
+                }
+            }
+            _ if config.signal != 0 => {
+                warn!("Command {key} has a signal, but only polling commands can be refreshed")
+            }
+            _ => {}
+        }
+
+        if config.timeout_ms != 0 && builder.output_type == OutputType::Watcher {
+            warn!("Command {key} has a timeout, but watchers run until they exit")
+        }
+
+        let (output_sender, mut outputs) = mpsc::unbounded_channel();
+        let output_sender = Arc::new(output_sender);
+
+        let (channel, subscription) = BiChannel::new(16);
+        let mut state = CommandState {
+            output_format: config.output_format,
+            last_line: Ok(Line::default()),
+            exit_status: None,
+            subscribers: FanOut::new(),
+            channel,
+            builder: Arc::clone(&builder),
+            refresh: Arc::clone(&refresh),
+        };
+
+        // The command is polled in the same task as the subscription, so it is killed when the module is aborted.
+        let run = builder.run(output_sender, &refresh);
+        tokio::pin!(run);
+        let mut finished = false;
+
+        // Wait for the first output, so the widgets are not empty until the command prints another line.
+        let first_output = tokio::time::timeout(INITIAL_OUTPUT_TIMEOUT, async {
+            select! {
+                output = outputs.recv() => Ok(output),
+                result = &mut run => {
+                    finished = true;
+                    result.map(|()| None)
+                }
+            }
+        });
+
+        match first_output.await {
+            Ok(output) => {
+                if let Some(output) = output? {
+                    state.apply(output);
+                }
+            }
+            Err(_) => debug!("Command {key} did not print anything in time"),
+        }
+        // A static command is done now, and its last line is what it shows.
+        while let Ok(output) = outputs.try_recv() {
+            state.apply(output);
+        }
+
+        for data_request in requests.iter_mut() {
+            for request in data_request.data_fields.iter_mut() {
+                match request {
+                    Request::Request(RequestField::Command(command_field)) => {
+                        let command_field = *command_field;
+                        if state.output_format.provides(command_field) {
+                            state
+                                .subscribers
+                                .subscribe(command_field, data_request.id.clone());
+                        }
+
+                        match state.value(command_field) {
+                            Ok(data) => request.resolve(ModuleData::new(Data::Command(data))),
+                            Err(error) => request.reject(error),
+                        }
+                    }
+                    _ => request.reject_invalid(),
+                }
+            }
+        }
+
+        yield_channel.send(ModuleYield {
+            subscription: Some(subscription),
+            fulfilled_requests: requests,
+        })?;
+
+        loop {
+            select! {
+                result = &mut run, if !finished => {
+                    finished = true;
+                    result?;
+                    debug!("Command {key} finished");
+                }
+                Some(output) = outputs.recv() => state.update(output, &key).await,
+                Some(()) = async {
+                    match signal.as_mut() {
+                        Some(s) => s.recv().await,
+                        None => std::future::pending().await,
+                    }
+                } => refresh.notify_one(),
+                message = state.channel.receiver.recv_async() => match message {
+                    Ok(m) => state.handle_message(m).await,
+                    Err(_) => bail!("Command module subscription was dropped"),
+                },
+            }
+        }
     }
 }
 
+/// How the command is run, like `output_type = "watcher"` or `output_type = { poll = 5 }`
 #[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
-enum OutputTypeConfig {
+#[serde(rename_all = "snake_case")]
+pub enum OutputTypeConfig {
+    /// Run the command once
     #[default]
     Static,
+    /// Run the command once, and keep reading its output while it runs
     Watcher,
-    Poll(u64),
+    /// Run the command every this many seconds. 0 is rejected when the config is loaded.
+    Poll(NonZeroU64),
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -167,7 +823,7 @@ impl From<OutputTypeConfig> for OutputType {
         match value {
             OutputTypeConfig::Static => Self::Static,
             OutputTypeConfig::Watcher => Self::Watcher,
-            OutputTypeConfig::Poll(s) => Self::Poll(Duration::from_secs(s)),
+            OutputTypeConfig::Poll(s) => Self::Poll(Duration::from_secs(s.get())),
         }
     }
 }
@@ -184,7 +840,7 @@ impl From<OutputTypeConfig> for OutputType {
 
 #[derive(Debug, thiserror::Error)]
 pub enum CommandError {
-    #[error("(0)")]
+    #[error("{0}")]
     Io(#[from] tokio::io::Error),
     #[error("Child stdout is missing!")]
     MissingStdout,
@@ -197,3 +853,113 @@ impl<T> From<mpsc::error::SendError<T>> for CommandError {
     }
 }
 type CommandResult<T> = std::result::Result<T, CommandError>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn streams_watcher_lines() {
+        let widget = ModuleId::new("test/command").unwrap();
+        let config = CommandConfig {
+            command: Some("echo first; sleep 0.1; echo second; sleep 10".to_owned()),
+            output_type: Some(OutputTypeConfig::Watcher),
+            ..Default::default()
+        };
+
+        let (yield_sender, mut yields) = mpsc::unbounded_channel();
+        let requests = vec![DataRequest {
+            id: widget.clone(),
+            data_fields: vec![Request::Request(RequestField::Command(
+                CommandField::OUTPUT,
+            ))],
+        }];
+        let module = tokio::spawn(CommandModule::main(config, requests, yield_sender));
+
+        let line = |text: &str| {
+            Data::Command(CommandData {
+                field: CommandField::OUTPUT,
+                value: Variant::String(text.to_owned()),
+                status: None,
+            })
+        };
+
+        let yielded = yields.recv().await.unwrap();
+        assert_eq!(
+            yielded.fulfilled_requests[0].data_fields[0],
+            Request::Fulfilled(ModuleData::new(line("first")))
+        );
+
+        let channel = yielded.subscription.unwrap();
+        let ModuleOutput::Data(data) = channel.receiver.recv_async().await.unwrap() else {
+            panic!("The command did not send its second line");
+        };
+        assert_eq!(data.specific_target, Some(widget));
+        assert_eq!(data.content, line("second"));
+
+        module.abort();
+    }
+
+    #[tokio::test]
+    async fn kills_commands_that_time_out() {
+        let config = CommandConfig {
+            command: Some("echo started; sleep 10".to_owned()),
+            timeout_ms: Some(100),
+            ..Default::default()
+        };
+
+        let (yield_sender, mut yields) = mpsc::unbounded_channel();
+        let requests = vec![DataRequest {
+            id: ModuleId::new("test/command").unwrap(),
+            data_fields: vec![Request::Request(RequestField::Command(
+                CommandField::EXIT_STATUS,
+            ))],
+        }];
+        let module = tokio::spawn(CommandModule::main(config, requests, yield_sender));
+
+        let yielded = yields.recv().await.unwrap();
+        assert_eq!(
+            yielded.fulfilled_requests[0].data_fields[0],
+            Request::Fulfilled(ModuleData::new(Data::Command(CommandData {
+                field: CommandField::EXIT_STATUS,
+                value: Variant::Iint(TIMED_OUT_STATUS.into()),
+                status: None,
+            })))
+        );
+
+        module.abort();
+    }
+
+    #[test]
+    fn rejects_polling_every_0_seconds() {
+        let poll = |json| serde_json::from_str::<OutputTypeConfig>(json);
+
+        assert!(poll(r#"{"poll": 0}"#).is_err());
+        assert_eq!(
+            OutputType::from(poll(r#"{"poll": 5}"#).unwrap()),
+            OutputType::Poll(Duration::from_secs(5))
+        );
+    }
+
+    #[test]
+    fn parses_json_lines() {
+        let line = Line::parse(
+            OutputFormat::Json,
+            r#"{"text": "vol", "percentage": 42.4, "status": "urgent", "muted": null}"#.to_owned(),
+        )
+        .unwrap();
+
+        let text = line.data(CommandField::new("text"));
+        assert_eq!(text.value, Variant::String("vol".to_owned()));
+        assert_eq!(text.status, Some(Status::Critical));
+        assert_eq!(line.data(CommandField::PERCENTAGE).percentage(), Some(42));
+        // Keys that are null or missing are empty
+        assert!(!line.data(CommandField::new("muted")).is_truthy());
+        assert!(!line.data(CommandField::new("tooltip")).is_truthy());
+
+        assert!(matches!(
+            Line::parse(OutputFormat::Json, "50%".to_owned()),
+            Err(ProviderError::Parse { .. })
+        ));
+    }
+}
@@ -0,0 +1,598 @@
+use std::process::Stdio;
+
+use halogen::plugin::{write_message, MessageReader};
+use halogen::{BarMessage, PluginMessage};
+use tokio::process::{Child, ChildStdin, ChildStdout, Command};
+
+use super::*;
+use halobar_core::client::ErrorChain;
+pub use halobar_core::modules::plugin::{PluginData, PluginField};
+
+config_struct! {
+    @known {Clone}
+    @config {Clone, PartialEq}
+    [Plugin]
+    @conf @config #[serde(flatten)]
+    @conf throttle: crate::throttle => Throttle,
+    // The plugin executable. It is looked up in PATH if it is not a path.
+    command: String = String::new(),
+    args: Vec<String> = Vec::new(),
+    env: AHashMap<String, String> = AHashMap::new(),
+    // How long the plugin has to declare its fields and send the values that the widgets request, altogether.
+    // This has to be shorter than the bar's start_timeout_seconds, which the bar waits for at most.
+    handshake_timeout_ms: u64 = 3000,
+}
+
+/// A running plugin process, speaking the protocol in [`halogen::plugin`]
+struct Plugin {
+    command: String,
+    child: Child,
+    /// The messages for the plugin, which a task writes to its stdin.
+    /// This way the module keeps reading the plugin's output while the plugin is not reading its input.
+    stdin: mpsc::UnboundedSender<BarMessage>,
+    stdout: MessageReader<ChildStdout>,
+    /// The fields that the plugin declared in its hello
+    fields: Vec<PluginField>,
+    /// The widgets that subscribed to each field, which get the values that are not for a specific widget
+    subscribers: FanOut<PluginField>,
+}
+impl Plugin {
+    /// Start the plugin. Its stderr is logged until it exits, and its stdin is written until the plugin is dropped.
+    fn spawn(config: &PluginKnown) -> R<Self> {
+        if config.command.is_empty() {
+            bail!("The plugin has no command");
+        }
+
+        let mut command = Command::new(&config.command);
+        command.args(&config.args);
+        command.envs(config.env.iter());
+
+        command.stdin(Stdio::piped());
+        pipe_output(&mut command);
+
+        let mut child = command.spawn()?;
+        if let Some(pid) = child.id() {
+            debug!("Spawned plugin {} with pid {pid}", config.command);
+        }
+
+        let stdin = child.stdin.take().ok_or_eyre("Plugin stdin is missing")?;
+        let stdout = child.stdout.take().ok_or_eyre("Plugin stdout is missing")?;
+
+        if let Some(stderr) = child.stderr.take() {
+            spawn_stderr_logger(format!("Plugin {}", config.command), stderr);
+        }
+
+        let (stdin_sender, stdin_receiver) = mpsc::unbounded_channel();
+        tokio::spawn(write_stdin(config.command.clone(), stdin, stdin_receiver));
+
+        Ok(Self {
+            command: config.command.clone(),
+            child,
+            stdin: stdin_sender,
+            stdout: MessageReader::new(stdout),
+            fields: Vec::new(),
+            subscribers: FanOut::new(),
+        })
+    }
+
+    /// Queue a message for the plugin. This returns an error if the plugin can't be written to anymore.
+    fn send(&self, message: BarMessage) -> R<()> {
+        trace!("Sending {message:?} to plugin {}", self.command);
+        self.stdin
+            .send(message)
+            .map_err(|_| eyre!("Plugin {} closed its stdin", self.command))
+    }
+
+    /// Receive the next message from the plugin. This returns an error if the plugin closed its stdout.
+    ///
+    /// This is cancel safe.
+    async fn receive(&mut self) -> R<PluginMessage> {
+        if let Some(message) = self.stdout.next().await? {
+            trace!("Received {message:?} from plugin {}", self.command);
+            return Ok(message);
+        }
+
+        match self.child.try_wait()? {
+            Some(status) => bail!("Plugin {} exited with {status}", self.command),
+            None => bail!("Plugin {} closed its stdout", self.command),
+        }
+    }
+
+    /// Wait for the plugin to declare its fields
+    async fn handshake(&mut self) -> R<()> {
+        match self.receive().await? {
+            PluginMessage::Hello { fields } => {
+                debug!("Plugin {} provides fields {fields:?}", self.command);
+                self.fields = fields.iter().map(|f| PluginField::new(f)).collect();
+                Ok(())
+            }
+            other => bail!(
+                "Plugin {} sent {other:?} instead of its hello",
+                self.command
+            ),
+        }
+    }
+
+    /// Subscribe a widget to the fields it requests.
+    ///
+    /// The fields that the plugin does not provide are rejected, and the rest stay pending until the plugin sends their values.
+    fn subscribe(&mut self, request: &mut DataRequest) -> R<()> {
+        let mut fields = Vec::with_capacity(request.data_fields.len());
+
+        for field in request.data_fields.iter_mut() {
+            match field {
+                Request::Request(RequestField::Plugin(f)) if self.fields.contains(f) => {
+                    self.subscribers.subscribe(*f, request.id.clone());
+                    fields.push(f.to_string())
+                }
+                Request::Request(r) => {
+                    *field = Request::Error(ProviderError::InvalidField(r.clone()));
+                }
+                _ => {}
+            }
+        }
+
+        if fields.is_empty() {
+            return Ok(());
+        }
+
+        self.send(BarMessage::Subscribe {
+            widget: request.id.to_string(),
+            fields,
+        })
+    }
+
+    /// Stop sending the values of these fields to a widget
+    fn unsubscribe(&mut self, widget: &ModuleId, fields: Vec<PluginField>) -> R<()> {
+        for field in fields.iter() {
+            self.subscribers.unsubscribe(field, widget);
+        }
+
+        self.send(BarMessage::Unsubscribe {
+            widget: widget.to_string(),
+            fields: fields.iter().map(|f| f.to_string()).collect(),
+        })
+    }
+
+    /// Turn a message from the plugin into output for the widgets.
+    ///
+    /// A value that is not for a specific widget is sent to every widget that subscribed to its field.
+    /// Values for fields that the plugin did not declare, and for widgets that did not subscribe to them, are dropped.
+    fn output(&self, message: PluginMessage) -> Vec<ModuleOutput> {
+        match message {
+            PluginMessage::Data {
+                widget,
+                field,
+                value,
+                status,
+            } => {
+                // The names come from the plugin, so they are looked up instead of being interned.
+                let Some(field) = self.fields.iter().copied().find(|f| f.as_str() == field) else {
+                    warn!(
+                        "Plugin {} sent data for field '{field}', which it did not declare",
+                        self.command
+                    );
+                    return Vec::new();
+                };
+                let content = Data::Plugin(PluginData {
+                    field,
+                    value,
+                    status,
+                });
+
+                match widget {
+                    Some(w) => match find_widget(self.subscribers.widgets(&field), &w) {
+                        Some(w) => vec![ModuleOutput::Data(ModuleData {
+                            specific_target: Some(w),
+                            content,
+                        })],
+                        None => {
+                            warn!(
+                                "Plugin {} sent data for widget '{w}', which did not subscribe to {field}",
+                                self.command
+                            );
+                            Vec::new()
+                        }
+                    },
+                    None => self
+                        .subscribers
+                        .fan_out(&field, &content)
+                        .into_iter()
+                        .map(ModuleOutput::Data)
+                        .collect(),
+                }
+            }
+            PluginMessage::Error { widget, message } => {
+                let widget = match widget {
+                    Some(w) => {
+                        let subscribed = self
+                            .subscribers
+                            .iter()
+                            .find_map(|(_, widgets)| find_widget(widgets, &w));
+                        let Some(subscribed) = subscribed else {
+                            warn!(
+                                "Plugin {} sent an error for widget '{w}', which is not subscribed",
+                                self.command
+                            );
+                            return Vec::new();
+                        };
+                        Some(subscribed)
+                    }
+                    None => None,
+                };
+
+                vec![ModuleOutput::Error {
+                    widget,
+                    error: ProviderError::Query {
+                        source: ErrorChain::msg(message),
+                    },
+                }]
+            }
+            PluginMessage::Hello { .. } => {
+                warn!("Plugin {} sent another hello, ignoring it", self.command);
+                Vec::new()
+            }
+        }
+    }
+}
+
+/// Write the messages for a plugin to its stdin until the plugin is dropped, or it stops reading
+async fn write_stdin(
+    command: String,
+    mut stdin: ChildStdin,
+    mut messages: mpsc::UnboundedReceiver<BarMessage>,
+) {
+    while let Some(message) = messages.recv().await {
+        if let Err(e) = write_message(&mut stdin, &message).await {
+            warn!("Failed to write to plugin {command}: {e}");
+            return;
+        }
+    }
+}
+
+/// Find the widget with this key among these widgets
+fn find_widget(widgets: &[ModuleId], key: &str) -> Option<ModuleId> {
+    widgets.iter().find(|w| w.key() == key).cloned()
+}
+
+/// Apply output from the plugin to the requests that are waiting for their initial values
+fn fulfill(requests: &mut [DataRequest], output: &ModuleOutput) {
+    let (widget, field) = match output {
+        ModuleOutput::Data(data) => (&data.specific_target, Some(data.content.field())),
+        ModuleOutput::Error { widget, .. } => (widget, None),
+    };
+
+    let requests = requests
+        .iter_mut()
+        .filter(|r| widget.as_ref().is_none_or(|w| *w == r.id));
+
+    for request in requests {
+        for request_field in request.data_fields.iter_mut() {
+            let requested = match request_field {
+                Request::Request(RequestField::Plugin(f)) => *f,
+                Request::Fulfilled(ModuleData {
+                    content: Data::Plugin(d),
+                    ..
+                }) => d.field,
+                _ => continue,
+            };
+
+            match output {
+                ModuleOutput::Data(data) if field == Some(requested.as_str()) => {
+                    request_field.resolve(ModuleData::new(data.content.clone()))
+                }
+                ModuleOutput::Error { error, .. } => request_field.reject(error.clone()),
+                _ => {}
+            }
+        }
+    }
+}
+
+/// A module that runs an external executable, which provides the data over the protocol in [`halogen::plugin`]
+pub struct PluginModule;
+impl ModuleDataProvider for PluginModule {
+    type ServerConfig = PluginConfig;
+    fn request_fields(format: &str) -> R<Vec<RequestField>> {
+        // The plugin declares its fields when it starts, so they are checked then.
+        request_variables(format, |var| RequestField::Plugin(PluginField::new(var)))
+    }
+    async fn main(
+        config: Self::ServerConfig,
+        mut requests: Vec<DataRequest>,
+        yield_channel: mpsc::UnboundedSender<ModuleYield>,
+    ) -> R<()> {
+        let config = config.into_known();
+        // The hello and the initial values share a deadline, so the module yields in time for the bar to start.
+        let deadline =
+            tokio::time::Instant::now() + Duration::from_millis(config.handshake_timeout_ms);
+
+        let mut plugin = Plugin::spawn(&config)?;
+
+        tokio::time::timeout_at(deadline, plugin.handshake())
+            .await
+            .map_err(|_| eyre!("Plugin {} did not send its hello in time", config.command))??;
+
+        for request in requests.iter_mut() {
+            plugin.subscribe(request)?;
+        }
+
+        // Wait for the initial values, so the widgets are not empty until the plugin sends an update.
+        let is_pending = |requests: &[DataRequest]| {
+            requests
+                .iter()
+                .flat_map(|r| r.data_fields.iter())
+                .any(|f| matches!(f, Request::Request(_)))
+        };
+
+        while is_pending(&requests) {
+            let Ok(message) = tokio::time::timeout_at(deadline, plugin.receive()).await else {
+                warn!(
+                    "Plugin {} did not send all the values that widgets request in time",
+                    config.command
+                );
+                break;
+            };
+
+            for output in plugin.output(message?) {
+                fulfill(&mut requests, &output);
+            }
+        }
+
+        for field in requests.iter_mut().flat_map(|r| r.data_fields.iter_mut()) {
+            if let Request::Request(_) = field {
+                field.reject(ProviderError::Timeout {
+                    source: ErrorChain::msg("The plugin did not send this value"),
+                });
+            }
+        }
+
+        let (channel, yield_subscription) = BiChannel::<ModuleOutput, ModuleMessage>::new(16);
+
+        // This always yields a subscription, because the plugin can send updates at any time.
+        yield_channel.send(ModuleYield {
+            subscription: Some(yield_subscription),
+            fulfilled_requests: requests,
+        })?;
+
+        loop {
+            select! {
+                message = plugin.receive() => {
+                    for output in plugin.output(message?) {
+                        channel.send(output).await;
+                    }
+                }
+
+                message = channel.receiver.recv_async() => {
+                    let Ok(message) = message else {
+                        bail!("Plugin module subscription was dropped");
+                    };
+
+                    match message {
+                        ModuleMessage::Event { widget, event } => {
+                            plugin.send(BarMessage::Event {
+                                widget: widget.to_string(),
+                                event,
+                            })?
+                        }
+                        ModuleMessage::Subscribe(mut request) => {
+                            plugin.subscribe(&mut request)?;
+
+                            for field in request.data_fields {
+                                if let Request::Error(error) = field {
+                                    channel
+                                        .send(ModuleOutput::Error {
+                                            widget: Some(request.id.clone()),
+                                            error,
+                                        })
+                                        .await;
+                                }
+                            }
+                        }
+                        ModuleMessage::Unsubscribe { widget, fields } => {
+                            let fields = fields
+                                .into_iter()
+                                .filter_map(|f| match f {
+                                    RequestField::Plugin(f) => Some(f),
+                                    _ => None,
+                                })
+                                .collect();
+
+                            plugin.unsubscribe(&widget, fields)?;
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(widget: &ModuleId, field: &str) -> DataRequest {
+        DataRequest {
+            id: widget.clone(),
+            data_fields: vec![Request::Request(RequestField::Plugin(PluginField::new(
+                field,
+            )))],
+        }
+    }
+
+    #[tokio::test]
+    async fn speaks_the_plugin_protocol() {
+        let widget = ModuleId::new("test/plugin").unwrap();
+        // Answers every subscribe with the value of the field, then echoes events back as data.
+        let script = r#"
+            printf '\001{"Hello":{"fields":["greeting"]}}\000'
+            while IFS= read -r -d '' message; do
+                case "$message" in
+                    *Subscribe*) printf '\001{"Data":{"field":"greeting","value":{"String":"hi"}}}\000' ;;
+                    *Click*) printf '\001{"Data":{"widget":"test/plugin","field":"greeting","value":{"String":"clicked"}}}\000' ;;
+                esac
+            done
+        "#;
+
+        let config = PluginConfig {
+            command: Some("bash".to_owned()),
+            args: Some(vec!["-c".to_owned(), script.to_owned()]),
+            ..Default::default()
+        };
+
+        let (yield_sender, mut yields) = mpsc::unbounded_channel();
+        let requests = vec![
+            request(&widget, "greeting"),
+            request(&ModuleId::new("test/plugin/1").unwrap(), "missing"),
+        ];
+        let module = tokio::spawn(PluginModule::main(config, requests, yield_sender));
+
+        let yielded = yields.recv().await.unwrap();
+        let fields = yielded
+            .fulfilled_requests
+            .iter()
+            .map(|r| r.data_fields[0].clone())
+            .collect::<Vec<_>>();
+
+        let greeting = |text: &str| {
+            Data::Plugin(PluginData {
+                field: PluginField::new("greeting"),
+                value: halogen::Variant::String(text.to_owned()),
+                status: None,
+            })
+        };
+        assert_eq!(
+            fields[0],
+            Request::Fulfilled(ModuleData::new(greeting("hi")))
+        );
+        assert!(matches!(
+            fields[1],
+            Request::Error(ProviderError::InvalidField(_))
+        ));
+
+        let channel = yielded.subscription.unwrap();
+        channel
+            .send(ModuleMessage::Event {
+                widget: widget.clone(),
+                event: Event::Click,
+            })
+            .await;
+
+        let ModuleOutput::Data(data) = channel.receiver.recv_async().await.unwrap() else {
+            panic!("The plugin did not echo the click");
+        };
+        assert_eq!(data.specific_target, Some(widget));
+        assert_eq!(data.content, greeting("clicked"));
+
+        module.abort();
+    }
+
+    #[tokio::test]
+    async fn reads_output_while_plugin_is_not_reading() {
+        let widget = ModuleId::new("test/plugin").unwrap();
+        // Stops reading its stdin after the subscribe, but keeps sending values.
+        let script = r#"
+            printf '\001{"Hello":{"fields":["greeting"]}}\000'
+            IFS= read -r -d '' message
+            printf '\001{"Data":{"field":"greeting","value":{"String":"hi"}}}\000'
+            sleep 0.5
+            printf '\001{"Data":{"field":"greeting","value":{"String":"still here"}}}\000'
+            sleep 10
+        "#;
+
+        let config = PluginConfig {
+            command: Some("bash".to_owned()),
+            args: Some(vec!["-c".to_owned(), script.to_owned()]),
+            ..Default::default()
+        };
+
+        let (yield_sender, mut yields) = mpsc::unbounded_channel();
+        let requests = vec![request(&widget, "greeting")];
+        let module = tokio::spawn(PluginModule::main(config, requests, yield_sender));
+        let channel = yields.recv().await.unwrap().subscription.unwrap();
+
+        // Enough events to fill the pipe to the plugin's stdin
+        let events = async {
+            for _ in 0..10_000 {
+                channel
+                    .send(ModuleMessage::Event {
+                        widget: widget.clone(),
+                        event: Event::Click,
+                    })
+                    .await;
+            }
+            channel.receiver.recv_async().await.unwrap()
+        };
+
+        let output = tokio::time::timeout(Duration::from_secs(5), events)
+            .await
+            .expect("The module stopped reading the plugin's output");
+        let ModuleOutput::Data(data) = output else {
+            panic!("The plugin did not send its value");
+        };
+        assert_eq!(
+            data.content,
+            Data::Plugin(PluginData {
+                field: PluginField::new("greeting"),
+                value: halogen::Variant::String("still here".to_owned()),
+                status: None,
+            })
+        );
+
+        module.abort();
+    }
+
+    #[tokio::test]
+    async fn drops_undeclared_fields() {
+        let widget = ModuleId::new("test/plugin").unwrap();
+        // Sends a field that it never declared before each value of the one it did.
+        let script = r#"
+            printf '\001{"Hello":{"fields":["greeting"]}}\000'
+            while IFS= read -r -d '' message; do
+                printf '\001{"Data":{"field":"secret","value":{"String":"leaked"}}}\000'
+                case "$message" in
+                    *Subscribe*) printf '\001{"Data":{"field":"greeting","value":{"String":"hi"}}}\000' ;;
+                    *Click*) printf '\001{"Data":{"field":"greeting","value":{"String":"clicked"}}}\000' ;;
+                esac
+            done
+        "#;
+
+        let config = PluginConfig {
+            command: Some("bash".to_owned()),
+            args: Some(vec!["-c".to_owned(), script.to_owned()]),
+            ..Default::default()
+        };
+
+        let (yield_sender, mut yields) = mpsc::unbounded_channel();
+        let requests = vec![request(&widget, "greeting")];
+        let module = tokio::spawn(PluginModule::main(config, requests, yield_sender));
+
+        let greeting = |text: &str| {
+            Data::Plugin(PluginData {
+                field: PluginField::new("greeting"),
+                value: halogen::Variant::String(text.to_owned()),
+                status: None,
+            })
+        };
+
+        let yielded = yields.recv().await.unwrap();
+        assert_eq!(
+            yielded.fulfilled_requests[0].data_fields,
+            [Request::Fulfilled(ModuleData::new(greeting("hi")))]
+        );
+
+        let channel = yielded.subscription.unwrap();
+        channel
+            .send(ModuleMessage::Event {
+                widget: widget.clone(),
+                event: Event::Click,
+            })
+            .await;
+
+        let ModuleOutput::Data(data) = channel.receiver.recv_async().await.unwrap() else {
+            panic!("The plugin did not send its value");
+        };
+        assert_eq!(data.content, greeting("clicked"));
+
+        module.abort();
+    }
+}
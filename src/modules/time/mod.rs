@@ -1,17 +1,23 @@
-use chrono::{format::StrftimeItems, DateTime, Local};
+use chrono::{format::StrftimeItems, DateTime, FixedOffset, Local, Utc};
 
 use super::*;
+pub use halobar_core::modules::time::TimeData;
 
 config_struct! {
     @known {Clone}
-    @config {Clone}
+    @config {Clone, PartialEq}
     [Time]
+    @conf @config #[serde(flatten)]
+    @conf throttle: crate::throttle => Throttle,
     // format: String = "%I:%M:%S %P".to_owned(),
     // format_alt: String = "%a, %m/%d @ %I:%M:%S %P".to_owned(),
     interval_ms: u64 = 1000,
+    // Show the time in UTC instead of the local timezone
+    utc: bool = false,
 }
 
 /// A newtype struct so I don't get confused when referring to types
+#[derive(Debug, PartialEq)]
 struct Strftime(String);
 impl<'a> Strftime {
     pub fn consume(self) -> String {
@@ -20,45 +26,91 @@ impl<'a> Strftime {
     pub fn as_str(&'a self) -> &'a str {
         self.0.as_str()
     }
-    pub fn format_time(&self, time: &DateTime<chrono::Local>) -> String {
+    pub fn format_time(&self, time: &DateTime<FixedOffset>) -> String {
         time.format(&self.0).to_string()
     }
 }
 
 pub struct Time {
-    format_strings: AHashMap<ModuleId, Strftime>,
+    /// Widgets with the same format share it, so each format is only formatted once per tick.
+    format_strings: FanOut<Strftime>,
     // format_alt: String,
     interval: Duration,
-    channel: BiChannel<ModuleData, Event>,
+    utc: bool,
+    channel: BiChannel<ModuleOutput, ModuleMessage>,
     // state: Mutex<FormatState>,
 }
 impl Time {
-    fn get_time() -> DateTime<Local> {
-        Local::now()
+    fn get_time(utc: bool) -> DateTime<FixedOffset> {
+        match utc {
+            true => Utc::now().fixed_offset(),
+            false => Local::now().fixed_offset(),
+        }
     }
 
     async fn tick(&mut self) {
-        let time = Self::get_time();
+        let time = Self::get_time(self.utc);
 
         let mut data = self
             .format_strings
             .iter()
-            .map(|(id, stime)| (id.clone(), stime.format_time(&time)))
-            .map(|(id, timestr)| ModuleData {
-                specific_target: Some(id),
-                content: Data::Time(TimeData(timestr)),
+            .flat_map(|(stime, _)| {
+                let content = Data::Time(TimeData(stime.format_time(&time)));
+                self.format_strings.fan_out(stime, &content)
             })
             .map(|d| async {
                 let mod_id = d.specific_target.clone().unwrap();
-                let send = self.channel.send(d).await;
+                let send = self.channel.send(d.into()).await;
 
                 (send, mod_id)
             })
             .collect::<FuturesUnordered<_>>();
 
+        let mut unsent = Vec::new();
         while let Some((was_sent, module)) = data.next().await {
             if !was_sent {
-                self.format_strings.remove(&module);
+                unsent.push(module);
+            }
+        }
+        drop(data);
+
+        for module in unsent {
+            self.format_strings.remove_widget(&module);
+        }
+    }
+
+    /// Handle a message from the mux while running
+    async fn handle_message(&mut self, message: ModuleMessage) {
+        match message {
+            ModuleMessage::Event { event, .. } => trace!("Time module ignoring event {event:?}"),
+            ModuleMessage::Subscribe(request) => {
+                for field in request.data_fields {
+                    let Request::Request(RequestField::Time(strf)) = field else {
+                        warn!("Time module received invalid subscription: {field:?}");
+                        let error = ModuleOutput::Error {
+                            widget: Some(request.id.clone()),
+                            error: ProviderError::InvalidRequest(Box::new(field)),
+                        };
+                        self.channel.send(error).await;
+                        continue;
+                    };
+
+                    let stime = Strftime(strf);
+                    let data = ModuleData {
+                        specific_target: Some(request.id.clone()),
+                        content: Data::Time(TimeData(stime.format_time(&Self::get_time(self.utc)))),
+                    };
+
+                    self.format_strings.subscribe(stime, request.id.clone());
+                    self.channel.send(data.into()).await;
+                }
+            }
+            ModuleMessage::Unsubscribe { widget, fields } => {
+                for field in fields {
+                    if let RequestField::Time(strf) = field {
+                        self.format_strings.unsubscribe(&Strftime(strf), &widget);
+                    }
+                }
             }
         }
     }
@@ -67,6 +119,19 @@ impl Time {
 impl ModuleDataProvider for Time {
     type ServerConfig = TimeConfig;
 
+    /// The whole format string is a strftime string.
+    fn request_fields(format: &str) -> R<Vec<RequestField>> {
+        Ok(vec![RequestField::Time(format.to_owned())])
+    }
+
+    /// The only field is `time`, which is the widget's own formatted time, so rules never need anything else.
+    fn rule_fields(fields: &[&str]) -> R<Vec<RequestField>> {
+        match fields.iter().find(|f| **f != "time") {
+            Some(f) => bail!("Unknown time variable: {f}"),
+            None => Ok(Vec::new()),
+        }
+    }
+
     async fn main(
         config: Self::ServerConfig,
         mut requests: Vec<DataRequest>,
@@ -77,12 +142,13 @@ impl ModuleDataProvider for Time {
         let (channel, subscription) = BiChannel::new(24);
 
         let mut me = Self {
-            format_strings: AHashMap::new(),
+            format_strings: FanOut::new(),
             interval: Duration::from_millis(my_config.interval_ms),
+            utc: my_config.utc,
             channel,
         };
 
-        let time = Self::get_time();
+        let time = Self::get_time(me.utc);
 
         for data_request in requests.iter_mut() {
             for request in data_request.data_fields.iter_mut() {
@@ -95,7 +161,7 @@ impl ModuleDataProvider for Time {
                             content: Data::Time(TimeData(stime.format_time(&time))),
                         });
 
-                        me.format_strings.insert(data_request.id.clone(), stime);
+                        me.format_strings.subscribe(stime, data_request.id.clone());
                     }
                     _ => request.reject_invalid(),
                 }
@@ -109,8 +175,17 @@ impl ModuleDataProvider for Time {
 
         yield_channel.send(yields)?;
 
+        let mut interval = tokio::time::interval(me.interval);
+        interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
         loop {
-            join!(tokio::time::sleep(me.interval), me.tick());
+            select! {
+                _ = interval.tick() => me.tick().await,
+                message = me.channel.receiver.recv_async() => match message {
+                    Ok(m) => me.handle_message(m).await,
+                    Err(_) => bail!("Time module subscription was dropped"),
+                },
+            }
         }
     }
 }
@@ -144,6 +219,3 @@ impl ModuleDataProvider for Time {
 //         return Ok(false);
 //     }
 // }
-
-#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
-pub struct TimeData(pub String);
@@ -1,10 +1,11 @@
-pub mod types;
 mod xmlgen;
 
 use std::sync::atomic::AtomicBool;
 
 use super::*;
-use futures_util::future::BoxFuture;
+use futures_util::future::{AbortHandle, Abortable, BoxFuture};
+use halobar_core::client::ErrorChain;
+pub use halobar_core::modules::upower::{types, UpowerData, UpowerDataDiscriminants};
 use types::*;
 use xmlgen::{display_device::DeviceProxy, keyboard::KbdBacklightProxy, upower::UPowerProxy};
 use zbus::{
@@ -14,8 +15,10 @@ use zbus::{
 
 config_struct! {
     @known {Clone}
-    @config {Clone}
+    @config {Clone, PartialEq}
     [Upower]
+    @conf @config #[serde(flatten)]
+    @conf throttle: crate::throttle => Throttle,
     device_path: String = String::new(),
 }
 
@@ -76,6 +79,8 @@ struct Upower<'c> {
     /// I use these containers for my own convenience.
     /// These hold the data that already was sent, but that is updated.
     props: RwLock<Vec<UpowerData>>,
+    /// The widgets that requested each property. Each property is only watched once, then fanned out to them.
+    requested: RwLock<FanOut<UpowerDataDiscriminants>>,
 }
 impl<'c> Upower<'c> {
     pub async fn new(conn: &'c Connection, device_path: String) -> R<Self> {
@@ -105,15 +110,16 @@ impl<'c> Upower<'c> {
             device,
             keyboard: OnceCell::new(),
             props: RwLock::new(Vec::new()),
+            requested: RwLock::new(FanOut::new()),
         })
     }
 
     /// This is a getter that ensures the keyboard proxy is init.
-    pub async fn get_keyboard(&'c self) -> zbus::Result<&'c Keyboard<'c>> {
+    pub async fn get_keyboard(&self) -> zbus::Result<&Keyboard<'c>> {
         match self.keyboard.get() {
             Some(k) => Ok(k),
             None => {
-                let keyboard = Keyboard::new(&self.conn).await?;
+                let keyboard = Keyboard::new(self.conn).await?;
                 self.keyboard.set(keyboard).map_err(|_| zbus::Error::Failure("Failed to initialize keyboard, failed to get field, but field was already set after initializing proxies!".to_owned()))?;
                 self.keyboard.get().ok_or(zbus::Error::Failure(
                     "Failed to initialize keyboard, field remained empty after init!".to_owned(),
@@ -123,7 +129,7 @@ impl<'c> Upower<'c> {
     }
 
     /// Resolve/reject a request. This mutates a reference.
-    pub async fn fulfill_initial_request(&'c self, request: &mut Request) -> R<()> {
+    pub async fn fulfill_initial_request(&self, request: &mut Request) -> R<()> {
         let discriminant = match request {
             Request::Request(RequestField::Upower(d)) => d,
             _ => {
@@ -154,15 +160,12 @@ impl<'c> Upower<'c> {
                             match $prop_getter.await {
                                 Ok(prop) => {
                                     // listener_set.push($listener_future);
-                                    {
-                                        let mut borrowed = self.props.write().await;
-                                        borrowed.push(UpowerData::$enum_arm(prop.clone()));
-                                    }
-                                    Some(UpowerData::$enum_arm(prop))
+                                    self.cache(UpowerData::$enum_arm(prop.clone())).await;
+                                    Ok(UpowerData::$enum_arm(prop))
                                 }
                                 Err(e) => {
                                     warn!("Error getting data for request {}: {e}", stringify!($enum_arm));
-                                    None
+                                    Err(ProviderError::from(e))
                                 }
                             }
                         }
@@ -204,255 +207,446 @@ impl<'c> Upower<'c> {
         };
 
         match data {
-            Some(d) => request.resolve(ModuleData::new(Data::Upower(d))),
-            None => request.reject(ProviderError::QueryError),
+            Ok(d) => request.resolve(ModuleData::new(Data::Upower(d))),
+            Err(e) => request.reject(e),
         }
 
         Ok(())
     }
-}
 
-/// This is literally just here so I can have the Upower struct just reference
-/// the connection so I don't have to mess with self-referential structs
-pub struct UpowerMod;
-impl ModuleDataProvider for UpowerMod {
-    type ServerConfig = UpowerConfig;
-    async fn main(
-        config: Self::ServerConfig,
-        mut requests: Vec<DataRequest>,
-        yield_channel: mpsc::UnboundedSender<ModuleYield>,
-    ) -> R<()> {
-        let my_config = config.into_known();
+    /// Replace the cached value of this property, so later requests for it get the current value.
+    async fn cache(&self, data: UpowerData) {
+        let discriminant = UpowerDataDiscriminants::from(&data);
+        let mut props = self.props.write().await;
 
-        let conn = crate::globals::get_zbus_system().await?;
-
-        let upower = Upower::new(&conn, my_config.device_path).await?;
+        match props
+            .iter_mut()
+            .find(|d| UpowerDataDiscriminants::from(&**d) == discriminant)
+        {
+            Some(old) => *old = data,
+            None => props.push(data),
+        }
+    }
 
-        for data_request in requests.iter_mut() {
-            let mut pending_requests = Vec::with_capacity(data_request.data_fields.len());
-            std::mem::swap(&mut pending_requests, &mut data_request.data_fields);
+    /// Cache a property that changed, then send it to every widget that requested it.
+    async fn send_changed(&self, sender: &PropSender, data: UpowerData) -> R<()> {
+        self.cache(data.clone()).await;
 
-            let mut requests = pending_requests
-                .into_iter()
-                .map(|req| async {
-                    let mut req = req;
-                    if let Err(e) = upower.fulfill_initial_request(&mut req).await {
-                        warn!("Error fulfilling initial request for {req:?}: {e}");
-                        req.reject(ProviderError::QueryError);
-                    }
-                    req
-                })
-                .collect::<FuturesUnordered<_>>();
+        let discriminant = UpowerDataDiscriminants::from(&data);
+        let content = Data::Upower(data);
 
-            while let Some(request) = requests.next().await {
-                data_request.data_fields.push(request);
-            }
+        let requested = self.requested.read().await;
+        for widget in requested.widgets(&discriminant) {
+            let data = ModuleData {
+                specific_target: Some(widget.clone()),
+                content: content.clone(),
+            };
+            sender.send((discriminant, widget.clone()), data.into())?;
         }
 
-        let (channel, yield_subscription) = BiChannel::<ModuleData, Event>::new(16);
-
-        // I need to loop over all the requested types one more time because
-        // I could not return futures referencing self in upower.fulfill_initial_request()
-        // and I want this to be single-threaded.
-        let props = {
-            upower
-                .props
-                .read()
-                .await
-                .iter()
-                .filter_map(|prop| {
-                    let discriminant: UpowerDataDiscriminants = prop.try_into().ok()?;
-                    Some(discriminant)
-                })
-                .collect::<Box<[_]>>()
-        };
+        Ok(())
+    }
+
+    /// Tell every widget that requested this property that it can't be watched anymore.
+    ///
+    /// This replaces any pending value, because it is outdated.
+    async fn send_error(
+        &self,
+        sender: &PropSender,
+        discriminant: UpowerDataDiscriminants,
+        error: ProviderError,
+    ) -> R<()> {
+        let requested = self.requested.read().await;
+        for widget in requested.widgets(&discriminant) {
+            let output = ModuleOutput::Error {
+                widget: Some(widget.clone()),
+                error: error.clone(),
+            };
+            sender.send((discriminant, widget.clone()), output)?;
+        }
 
-        let mut prop_futures: FuturesUnordered<BoxFuture<R<()>>> = FuturesUnordered::new();
+        Ok(())
+    }
 
+    /// Get a future that sends this property to the widgets that requested it whenever it changes.
+    ///
+    /// This returns `None` for the properties that never change.
+    fn watch<'a>(
+        &'a self,
+        discriminant: UpowerDataDiscriminants,
+        sender: &'a PropSender,
+    ) -> Option<BoxFuture<'a, R<()>>> {
         macro_rules! typical_stream {
             ($stream:expr => $data_variant:ident) => {
-                prop_futures.push(Box::pin(async {
+                Box::pin(async move {
                     let mut stream = $stream.await;
 
                     while let Some(p) = stream.next().await {
                         let prop = p.get().await?;
-                        channel
-                            .sender
-                            .send_async(ModuleData::new(Data::Upower(UpowerData::$data_variant(
-                                prop,
-                            ))))
+                        self.send_changed(sender, UpowerData::$data_variant(prop))
                             .await?;
                     }
 
                     Ok::<(), Report>(())
-                }))
+                })
             };
         }
 
-        props.into_iter().for_each(|disc| match disc {
-            // These are static, so I don't need to wait for changes
+        let future: BoxFuture<'a, R<()>> = match discriminant {
             UpowerDataDiscriminants::CriticalAction
-            | UpowerDataDiscriminants::KeyboardBrightnessMax => {}
+            | UpowerDataDiscriminants::KeyboardBrightnessMax => return None,
 
             UpowerDataDiscriminants::Energy => {
-                typical_stream!(upower.device.receive_energy_changed() => Energy)
+                typical_stream!(self.device.receive_energy_changed() => Energy)
             }
             UpowerDataDiscriminants::EnergyRate => {
-                typical_stream!(upower.device.receive_energy_rate_changed() => EnergyRate)
+                typical_stream!(self.device.receive_energy_rate_changed() => EnergyRate)
             }
             UpowerDataDiscriminants::Icon => {
-                typical_stream!(upower.device.receive_icon_name_changed() => Icon)
+                typical_stream!(self.device.receive_icon_name_changed() => Icon)
             }
             UpowerDataDiscriminants::Percentage => {
-                typical_stream!(upower.device.receive_percentage_changed() => Percentage)
+                typical_stream!(self.device.receive_percentage_changed() => Percentage)
             }
             UpowerDataDiscriminants::State => {
-                typical_stream!(upower.device.receive_state_changed() => State)
+                typical_stream!(self.device.receive_state_changed() => State)
             }
-            UpowerDataDiscriminants::Time => prop_futures.push(Box::pin(async {
-                let mut empty_stream = upower.device.receive_time_to_empty_changed().await;
-                let mut full_stream = upower.device.receive_time_to_full_changed().await;
+            UpowerDataDiscriminants::Time => Box::pin(async move {
+                let mut empty_stream = self.device.receive_time_to_empty_changed().await;
+                let mut full_stream = self.device.receive_time_to_full_changed().await;
                 loop {
                     let new_time = select! {
                         Some(empty) = empty_stream.next() => {
-                            UpowerData::time_property_changed(empty, true).await?
+                            time_property_changed(empty, true).await?
                         }
 
                         Some(full) = full_stream.next() => {
-                            UpowerData::time_property_changed(full, false).await?
+                            time_property_changed(full, false).await?
                         }
                     };
 
                     if let Some(new) = new_time {
-                        channel
-                            .sender
-                            .send_async(ModuleData::new(Data::Upower(new)))
-                            .await?;
+                        self.send_changed(sender, new).await?;
                     }
                 }
-                // Ok::<(), Report>(())
-            })),
+            }),
             UpowerDataDiscriminants::DeviceType => {
-                typical_stream!(upower.device.receive_type__changed() => DeviceType)
+                typical_stream!(self.device.receive_type__changed() => DeviceType)
             }
             UpowerDataDiscriminants::WarningLevel => {
-                typical_stream!(upower.device.receive_warning_level_changed() => WarningLevel)
+                typical_stream!(self.device.receive_warning_level_changed() => WarningLevel)
             }
-            UpowerDataDiscriminants::KeyboardBrightnessPercentage => {
-                prop_futures.push(Box::pin(async {
-                    let keyboard = upower.get_keyboard().await?;
-                    let mut stream = keyboard.keyboard.receive_brightness_changed().await?;
-
-                    while let Some(b) = stream.next().await {
-                        let new_brightness = b.args()?.value;
-                        let percent = keyboard
-                            .calc_brightness_percent(new_brightness)
-                            .ok_or_else(|| {
-                                zbus::Error::Failure(format!(
-                                    "Percentage int {new_brightness} is too big!"
-                                ))
-                            })?;
-
-                        channel
-                            .sender
-                            .send_async(ModuleData::new(Data::Upower(
-                                UpowerData::KeyboardBrightnessPercentage(percent),
-                            )))
-                            .await?;
-                    }
+            UpowerDataDiscriminants::KeyboardBrightnessPercentage => Box::pin(async move {
+                let keyboard = self.get_keyboard().await?;
+                let mut stream = keyboard.keyboard.receive_brightness_changed().await?;
 
-                    Ok(())
-                }))
-            }
+                while let Some(b) = stream.next().await {
+                    let new_brightness = b.args()?.value;
+                    let percent = keyboard
+                        .calc_brightness_percent(new_brightness)
+                        .ok_or_else(|| {
+                            zbus::Error::Failure(format!(
+                                "Percentage int {new_brightness} is too big!"
+                            ))
+                        })?;
+
+                    self.send_changed(sender, UpowerData::KeyboardBrightnessPercentage(percent))
+                        .await?;
+                }
 
-            UpowerDataDiscriminants::KeyboardBrightness => prop_futures.push(Box::pin(async {
-                let keyboard = upower.get_keyboard().await?;
+                Ok(())
+            }),
+            UpowerDataDiscriminants::KeyboardBrightness => Box::pin(async move {
+                let keyboard = self.get_keyboard().await?;
                 let mut stream = keyboard.keyboard.receive_brightness_changed().await?;
 
                 while let Some(b) = stream.next().await {
                     let new_brightness = b.args()?.value;
-
-                    channel
-                        .sender
-                        .send_async(ModuleData::new(Data::Upower(
-                            UpowerData::KeyboardBrightness(new_brightness),
-                        )))
+                    self.send_changed(sender, UpowerData::KeyboardBrightness(new_brightness))
                         .await?;
                 }
 
                 Ok(())
-            })),
-        });
-
-        let subscription = if prop_futures.is_empty() {
-            None
-        } else {
-            Some(yield_subscription)
+            }),
         };
 
+        Some(future)
+    }
+}
+
+/// This is literally just here so I can have the Upower struct just reference
+/// the connection so I don't have to mess with self-referential structs
+pub struct UpowerMod;
+impl ModuleDataProvider for UpowerMod {
+    type ServerConfig = UpowerConfig;
+    fn request_fields(format: &str) -> R<Vec<RequestField>> {
+        let segments = FmtSegmentVec::new(format)?;
+
+        let mut fields = Vec::new();
+        for segment in segments.segments() {
+            let halobar_config::fmt::Segment::Variable(var) = segment else {
+                continue;
+            };
+
+            let discriminant = UpowerDataDiscriminants::from_str(&var.ident)
+                .map_err(|_| eyre!("Unknown upower variable: {}", var.ident))?;
+
+            let field = RequestField::Upower(discriminant);
+            if !fields.contains(&field) {
+                fields.push(field);
+            }
+        }
+
+        Ok(fields)
+    }
+    async fn main(
+        config: Self::ServerConfig,
+        mut requests: Vec<DataRequest>,
+        yield_channel: mpsc::UnboundedSender<ModuleYield>,
+    ) -> R<()> {
+        let my_config = config.into_known();
+
+        let conn = crate::globals::get_zbus_system().await?;
+
+        let upower = Upower::new(&conn, my_config.device_path).await?;
+
+        for data_request in requests.iter_mut() {
+            let mut pending_requests = Vec::with_capacity(data_request.data_fields.len());
+            std::mem::swap(&mut pending_requests, &mut data_request.data_fields);
+
+            let mut requests = pending_requests
+                .into_iter()
+                .map(|req| async {
+                    let mut req = req;
+                    if let Err(e) = upower.fulfill_initial_request(&mut req).await {
+                        warn!("Error fulfilling initial request for {req:?}: {e}");
+                        req.reject(ProviderError::query(&e));
+                    }
+                    req
+                })
+                .collect::<FuturesUnordered<_>>();
+
+            while let Some(request) = requests.next().await {
+                data_request.data_fields.push(request);
+            }
+        }
+
+        let (channel, yield_subscription) = BiChannel::<ModuleOutput, ModuleMessage>::new(16);
+        // Energy and energy rate can change in bursts, and only the latest value of each matters.
+        let (coalescing, pump) = CoalescingSender::new(Arc::clone(&channel.sender));
+
+        let mut subscriptions = Subscriptions::new(&upower, &coalescing);
+        for request in requests.iter() {
+            subscriptions
+                .subscribe(request.id.clone(), fulfilled_fields(request))
+                .await;
+        }
+
+        // This always yields a subscription, because widgets can subscribe to more fields later.
         yield_channel.send(ModuleYield {
-            subscription,
+            subscription: Some(yield_subscription),
             fulfilled_requests: requests,
         })?;
 
-        if prop_futures.is_empty() {
-            return Ok(());
-        }
-
-        prop_futures.push(Box::pin(async {
+        let battery = async {
             let mut battery_sub = upower.upower.receive_on_battery_changed().await;
 
             while let Some(b) = battery_sub.next().await {
                 let on_battery = b.get().await?;
                 set_on_battery(on_battery);
             }
-            Ok(())
-        }));
+            Ok::<(), Report>(())
+        };
+        let mut battery_running = true;
 
-        while let Some(returned) = prop_futures.next().await {
-            match returned {
-                Ok(()) => warn!("A upower property stream stopped responding!"),
-                Err(e) => warn!("Error occured in upower stream: {e}"),
-            };
-        }
+        let pump = pump.run();
+        tokio::pin!(battery, pump);
 
-        Err(Report::msg("All upower subscriptions stopped responding!"))
+        loop {
+            select! {
+                Some(returned) = subscriptions.streams.next() => {
+                    // Streams that were stopped on purpose are aborted.
+                    let Ok((discriminant, result)) = returned else {
+                        continue;
+                    };
+
+                    subscriptions.handles.remove(&discriminant);
+                    let error = match result {
+                        Ok(()) => {
+                            warn!("The upower {discriminant:?} stream stopped responding!");
+                            ProviderError::Unavailable {
+                                service: "Upower".to_owned(),
+                                source: ErrorChain::msg(format!("The {discriminant:?} stream stopped responding")),
+                            }
+                        }
+                        Err(e) => {
+                            warn!("Error occured in upower {discriminant:?} stream: {e}");
+                            ProviderError::query(&e)
+                        }
+                    };
+                    upower.send_error(&coalescing, discriminant, error).await?;
+
+                    if subscriptions.streams.is_empty() {
+                        bail!("All upower subscriptions stopped responding!");
+                    }
+                }
+
+                message = channel.receiver.recv_async() => {
+                    let Ok(message) = message else {
+                        bail!("Upower module subscription was dropped");
+                    };
+
+                    match message {
+                        ModuleMessage::Event { event, .. } => trace!("Upower module ignoring event {event:?}"),
+                        ModuleMessage::Subscribe(mut request) => {
+                            for field in request.data_fields.iter_mut() {
+                                if let Err(e) = upower.fulfill_initial_request(field).await {
+                                    warn!("Error fulfilling request for {field:?}: {e}");
+                                    field.reject(ProviderError::query(&e));
+                                }
+
+                                let output = match field {
+                                    Request::Fulfilled(data) => {
+                                        data.specific_target = Some(request.id.clone());
+                                        ModuleOutput::Data(data.clone())
+                                    }
+                                    Request::Error(error) => ModuleOutput::Error {
+                                        widget: Some(request.id.clone()),
+                                        error: error.clone(),
+                                    },
+                                    Request::Request(_) => continue,
+                                };
+                                channel.send(output).await;
+                            }
+
+                            subscriptions
+                                .subscribe(request.id.clone(), fulfilled_fields(&request))
+                                .await;
+                        }
+                        ModuleMessage::Unsubscribe { widget, fields } => {
+                            let fields = fields
+                                .into_iter()
+                                .filter_map(|f| match f {
+                                    RequestField::Upower(d) => Some(d),
+                                    _ => None,
+                                })
+                                .collect::<Vec<_>>();
+
+                            subscriptions.unsubscribe(&widget, &fields).await;
+                        }
+                    }
+                }
+
+                result = &mut battery, if battery_running => {
+                    battery_running = false;
+                    match result {
+                        Ok(()) => warn!("The upower on-battery stream stopped responding!"),
+                        Err(e) => warn!("Error occured in upower on-battery stream: {e}"),
+                    }
+                }
+
+                result = &mut pump => {
+                    debug!("Upower data channel: {}", coalescing.stats());
+                    result?;
+                    bail!("Upower data channel stopped");
+                }
+            }
+        }
     }
 }
 
-#[derive(Debug, Clone, PartialEq, strum_macros::EnumDiscriminants)]
-#[strum_discriminants(derive(Hash))]
-pub enum UpowerData {
-    Energy(f64),
-    EnergyRate(f64),
-    Icon(String),
-    Percentage(Percentage),
-    State(BatteryState),
-    Time(Duration),
-    /// Usually this can only change when using DisplayDevice.
-    /// It cannot change when the user selected a custom path.
-    DeviceType(DeviceType),
-    WarningLevel(WarningLevel),
-
-    CriticalAction(CriticalAction),
-    KeyboardBrightnessPercentage(Percentage),
-    KeyboardBrightness(i32),
-    KeyboardBrightnessMax(i32),
+/// The upower fields that were fulfilled in this request
+fn fulfilled_fields(request: &DataRequest) -> Vec<UpowerDataDiscriminants> {
+    request
+        .data_fields
+        .iter()
+        .filter_map(|field| match field {
+            Request::Fulfilled(ModuleData {
+                content: Data::Upower(data),
+                ..
+            }) => Some(data.into()),
+            _ => None,
+        })
+        .collect()
 }
-impl UpowerData {
-    pub async fn time_property_changed(
-        prop: PropertyChanged<'_, i64>,
-        is_discharging_prop: bool,
-    ) -> zbus::Result<Option<Self>> {
-        // if it is not on battery, but the prop is not the battery prop for example
-        if on_battery() != is_discharging_prop {
-            return Ok(None);
+
+/// Only the latest value of each property is kept for each widget
+type PropSender = CoalescingSender<(UpowerDataDiscriminants, ModuleId), ModuleOutput>;
+type PropStream<'a> = Abortable<BoxFuture<'a, (UpowerDataDiscriminants, R<()>)>>;
+
+/// The property streams that keep the widgets updated.
+struct Subscriptions<'a, 'c> {
+    upower: &'a Upower<'c>,
+    sender: &'a PropSender,
+    streams: FuturesUnordered<PropStream<'a>>,
+    /// The handles of the streams that are running, so they can be stopped when no widget needs them anymore.
+    handles: AHashMap<UpowerDataDiscriminants, AbortHandle>,
+}
+impl<'a, 'c> Subscriptions<'a, 'c> {
+    fn new(upower: &'a Upower<'c>, sender: &'a PropSender) -> Self {
+        Self {
+            upower,
+            sender,
+            streams: FuturesUnordered::new(),
+            handles: AHashMap::new(),
         }
+    }
+
+    /// Keep track of the fields that a widget requested, and start watching the ones that are not watched yet.
+    async fn subscribe(&mut self, widget: ModuleId, fields: Vec<UpowerDataDiscriminants>) {
+        let mut requested = self.upower.requested.write().await;
+
+        for field in fields {
+            requested.subscribe(field, widget.clone());
+
+            // A stream might have stopped by itself, so this does not depend on whether the field was requested before.
+            if self.handles.contains_key(&field) {
+                continue;
+            }
+
+            // These are static, so I don't need to wait for changes
+            let Some(stream) = self.upower.watch(field, self.sender) else {
+                continue;
+            };
+
+            debug!("Watching upower {field:?}");
+            let (handle, registration) = AbortHandle::new_pair();
+            let stream: BoxFuture<_> = Box::pin(async move { (field, stream.await) });
+            self.streams.push(Abortable::new(stream, registration));
+            self.handles.insert(field, handle);
+        }
+    }
+
+    /// Forget that a widget requested these fields, and stop watching the ones that no widget requests anymore.
+    async fn unsubscribe(&mut self, widget: &ModuleId, fields: &[UpowerDataDiscriminants]) {
+        let mut requested = self.upower.requested.write().await;
 
-        let property = prop.get().await?;
+        for field in fields {
+            if !requested.unsubscribe(field, widget) {
+                continue;
+            }
 
-        let time_seconds = Duration::from_secs(property.unsigned_abs());
+            if let Some(handle) = self.handles.remove(field) {
+                debug!("No widgets need upower {field:?} anymore, stopping its stream");
+                handle.abort();
+            }
+        }
+    }
+}
 
-        Ok(Some(Self::Time(time_seconds)))
+/// Get the time until the battery is empty or full. This is None if the changed property is not the relevant one.
+async fn time_property_changed(
+    prop: PropertyChanged<'_, i64>,
+    is_discharging_prop: bool,
+) -> zbus::Result<Option<UpowerData>> {
+    // if it is not on battery, but the prop is not the battery prop for example
+    if on_battery() != is_discharging_prop {
+        return Ok(None);
     }
+
+    let property = prop.get().await?;
+
+    let time_seconds = Duration::from_secs(property.unsigned_abs());
+
+    Ok(Some(UpowerData::Time(time_seconds)))
 }
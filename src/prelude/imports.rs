@@ -8,13 +8,12 @@ pub(crate) use color_eyre::{
 pub(crate) use futures_util::{stream::FuturesUnordered, StreamExt, TryStreamExt};
 pub(crate) use halobar_config::{
     config_struct,
-    fmt::{FmtSegmentVec, FmtSegments, FnTable, FormatStr, HaloFormatter},
+    fmt::{FmtSegmentVec, FmtSegments, FnTable, FormatStr, HaloFormatter, Truthy},
 };
-pub(crate) use halogen::{Event, Variant};
+pub(crate) use halogen::{Event, Status, Variant};
 pub(crate) use nix::errno::Errno;
-pub(crate) use once_cell::sync::{Lazy, OnceCell};
+pub(crate) use once_cell::sync::OnceCell;
 pub(crate) use serde::{Deserialize, Serialize};
-pub(crate) use serde_repr::{Deserialize_repr, Serialize_repr};
 pub(crate) use size::Size;
 pub(crate) use smart_default::SmartDefault;
 pub(crate) use std::{
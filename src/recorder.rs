@@ -0,0 +1,375 @@
+//! Record the data that goes to the frontend, and replay it later without running any providers.
+//!
+//! Recordings are JSON lines, one [`Record`] per line.
+
+use std::io::{BufRead, Write};
+
+use crate::modules::{self, Data, ModuleData, ModuleOutput, ModuleType};
+use crate::prelude::*;
+use crate::to_frontend::{FrontendSender, ModuleStatus, WidgetLayout, WidgetUpdate};
+use halobar_core::client::{ErrorChain, ProviderError};
+
+/// A single line of a recording
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Record {
+    /// The time since the recording started
+    pub elapsed_ms: u64,
+    #[serde(flatten)]
+    pub content: RecordContent,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum RecordContent {
+    /// The widgets on the bar changed
+    Layout { widgets: Vec<WidgetLayout> },
+    /// A widget received data
+    Data {
+        widget: ModuleId,
+        module_type: ModuleType,
+        content: Data,
+    },
+    /// A widget received an error
+    Error {
+        widget: ModuleId,
+        /// The kind of the error, like `permission_denied`
+        error_kind: String,
+        /// The messages of the error and its sources
+        error: ErrorChain,
+    },
+}
+
+/// Writes every update that goes to the frontend to a file.
+///
+/// The file is written on a blocking thread, so the mux never waits for the disk.
+#[derive(Debug)]
+pub struct Recorder {
+    start: Instant,
+    sender: mpsc::UnboundedSender<Record>,
+}
+impl Recorder {
+    /// Create a new recording at this path, overwriting it if it exists
+    pub fn create(path: &Path) -> R<Self> {
+        let file = fs::File::create(path)?;
+        info!("Recording widget data to '{}'", path.display());
+
+        let (sender, receiver) = mpsc::unbounded_channel();
+        tokio::task::spawn_blocking(move || write_records(io::BufWriter::new(file), receiver));
+
+        Ok(Self {
+            start: Instant::now(),
+            sender,
+        })
+    }
+
+    /// Record an update that is being sent to the frontend
+    pub fn record(&self, update: &WidgetUpdate) {
+        let content = match update {
+            WidgetUpdate::Layout(widgets) => RecordContent::Layout {
+                widgets: widgets.clone(),
+            },
+            WidgetUpdate::Data(ModuleData {
+                specific_target: Some(widget),
+                content,
+            }) => RecordContent::Data {
+                widget: widget.clone(),
+                module_type: content.module_type(),
+                content: content.clone(),
+            },
+            WidgetUpdate::Data(_) => return,
+            WidgetUpdate::Errored { widget, error } => RecordContent::Error {
+                widget: widget.clone(),
+                error_kind: error.kind().to_owned(),
+                error: ErrorChain::new(error),
+            },
+        };
+
+        let record = Record {
+            elapsed_ms: self.start.elapsed().as_millis() as u64,
+            content,
+        };
+
+        if self.sender.send(record).is_err() {
+            error!("Failed to write to recording: the writer stopped");
+        }
+    }
+}
+
+/// Write each record to the recording until the [`Recorder`] is dropped
+fn write_records(
+    mut writer: io::BufWriter<fs::File>,
+    mut receiver: mpsc::UnboundedReceiver<Record>,
+) {
+    while let Some(record) = receiver.blocking_recv() {
+        if let Err(e) = write_record(&mut writer, &record) {
+            error!("Failed to write to recording: {e}");
+            continue;
+        }
+
+        // Flush whenever it caught up, so nothing is lost if the bar crashes, which is usually why you are recording.
+        if receiver.is_empty() {
+            if let Err(e) = writer.flush() {
+                error!("Failed to write to recording: {e}");
+            }
+        }
+    }
+}
+
+fn write_record(writer: &mut impl Write, record: &Record) -> R<()> {
+    serde_json::to_writer(&mut *writer, record)?;
+    writer.write_all(b"\n")?;
+    Ok(())
+}
+
+/// A recording that was read from a file
+#[derive(Debug)]
+pub struct Recording {
+    records: Vec<Record>,
+}
+impl Recording {
+    /// Read a recording from a file
+    pub fn open(path: &Path) -> R<Self> {
+        Self::read(io::BufReader::new(fs::File::open(path)?))
+    }
+
+    /// Read a recording, one record per line.
+    ///
+    /// Records that can't be read, like data for a widget or a field that is not in any layout before it, are skipped.
+    fn read(reader: impl BufRead) -> R<Self> {
+        let mut records = Vec::new();
+        for (index, line) in reader.lines().enumerate() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            match serde_json::from_str::<Record>(&line) {
+                Ok(record) => {
+                    if let RecordContent::Layout { ref widgets } = record.content {
+                        declare_fields(widgets);
+                    }
+                    records.push(record);
+                }
+                Err(e) => warn!("Skipping invalid record on line {}: {e}", index + 1),
+            }
+        }
+
+        Ok(Self { records })
+    }
+
+    /// Take the layout at the start of the recording, so it can be set before the mux starts.
+    pub fn take_initial_layout(&mut self) -> Option<Vec<WidgetLayout>> {
+        let Some(Record {
+            content: RecordContent::Layout { widgets },
+            ..
+        }) = self.records.first()
+        else {
+            return None;
+        };
+
+        let widgets = widgets.clone();
+        self.records.remove(0);
+        Some(widgets)
+    }
+
+    /// Feed the recording into the mux, keeping the original timing.
+    ///
+    /// `speed` is a multiplier, so 2.0 plays it twice as fast. If it is 0, everything is sent at once.
+    pub async fn replay(
+        self,
+        speed: f64,
+        message_sender: FrontendSender<ModuleOutput>,
+        status_sender: FrontendSender<ModuleStatus>,
+    ) -> R<()> {
+        let start = tokio::time::Instant::now();
+        info!("Replaying {} records", self.records.len());
+
+        for record in self.records {
+            if speed > 0.0 {
+                let elapsed = Duration::from_millis(record.elapsed_ms).div_f64(speed);
+                tokio::time::sleep_until(start + elapsed).await;
+            }
+
+            match record.content {
+                RecordContent::Layout { widgets } => {
+                    status_sender.send(ModuleStatus::Layout(widgets))?;
+                }
+                RecordContent::Data {
+                    widget,
+                    module_type,
+                    content,
+                } => {
+                    if content.module_type() != module_type {
+                        warn!("Recorded data for widget {widget} does not match its module type {module_type}");
+                    }
+
+                    message_sender.send(
+                        ModuleData {
+                            specific_target: Some(widget),
+                            content,
+                        }
+                        .into(),
+                    )?;
+                }
+                RecordContent::Error {
+                    widget,
+                    error_kind,
+                    error,
+                } => {
+                    message_sender.send(ModuleOutput::Error {
+                        widget: Some(widget),
+                        error: ProviderError::from_chain(&error_kind, error),
+                    })?;
+                }
+            }
+        }
+
+        info!("Finished replaying");
+        Ok(())
+    }
+}
+
+/// Create the fields that these widgets use, like loading them from the config does,
+/// so that the data for them can be read.
+fn declare_fields(widgets: &[WidgetLayout]) {
+    for widget in widgets {
+        let rules = widget
+            .visibility
+            .rules()
+            .map(|r| r.field.as_str())
+            .collect::<Vec<_>>();
+        let fields = modules::request_fields(&widget.module_type, &widget.format)
+            .and_then(|_| modules::rule_fields(&widget.module_type, &rules));
+
+        if let Err(e) = fields {
+            warn!("Recorded widget {} has invalid fields: {e}", widget.id);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::modules::plugin::PluginField;
+    use crate::modules::upower::{types::Percentage, UpowerData};
+
+    #[test]
+    fn record_roundtrip() {
+        let widget = ModuleId::new("test/upower").unwrap();
+        let record = Record {
+            elapsed_ms: 1500,
+            content: RecordContent::Data {
+                widget,
+                module_type: ModuleType::Upower,
+                content: Data::Upower(UpowerData::Percentage(Percentage::try_new(42).unwrap())),
+            },
+        };
+
+        let line = serde_json::to_string(&record).unwrap();
+        let parsed: Record = serde_json::from_str(&line).unwrap();
+
+        assert_eq!(parsed, record);
+    }
+
+    #[test]
+    fn replays_errors() {
+        let error = ProviderError::Parse {
+            what: "the output".to_owned(),
+            source: ErrorChain::msg("expected a number"),
+        };
+        let record = RecordContent::Error {
+            widget: ModuleId::new("test/errored").unwrap(),
+            error_kind: error.kind().to_owned(),
+            error: ErrorChain::new(&error),
+        };
+
+        let line = serde_json::to_string(&record).unwrap();
+        let RecordContent::Error {
+            error_kind,
+            error: chain,
+            ..
+        } = serde_json::from_str(&line).unwrap()
+        else {
+            panic!("The error was not recorded as an error");
+        };
+
+        assert_eq!(ProviderError::from_chain(&error_kind, chain), error);
+    }
+
+    #[test]
+    fn skips_widgets_that_are_not_in_the_layout() {
+        let layout = RecordContent::Layout {
+            widgets: vec![WidgetLayout {
+                id: ModuleId::new("test/recorded").unwrap(),
+                instance: "upower".into(),
+                module_type: ModuleType::Upower,
+                format: "{percentage}".to_owned(),
+                error_format: String::new(),
+                visibility: Default::default(),
+            }],
+        };
+        let data = |widget: &str| {
+            format!(
+                r#"{{"elapsed_ms":10,"kind":"data","widget":"{widget}","module_type":"Upower","content":{{"Upower":{{"Percentage":50}}}}}}"#
+            )
+        };
+        let recording = [
+            serde_json::to_string(&Record {
+                elapsed_ms: 0,
+                content: layout.clone(),
+            })
+            .unwrap(),
+            data("test/not_recorded"),
+            data("test/recorded"),
+        ]
+        .join("\n");
+
+        let records = Recording::read(recording.as_bytes()).unwrap().records;
+
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].content, layout);
+        let RecordContent::Data { ref widget, .. } = records[1].content else {
+            panic!("The data of the recorded widget was skipped");
+        };
+        assert_eq!(widget.key(), "test/recorded");
+        assert!(ModuleId::get("test/not_recorded").is_err());
+    }
+
+    #[test]
+    fn reads_fields_of_recorded_widgets() {
+        let layout = RecordContent::Layout {
+            widgets: vec![WidgetLayout {
+                id: ModuleId::new("test/recorded_plugin").unwrap(),
+                instance: "plugin".into(),
+                module_type: ModuleType::Plugin,
+                format: "{recorded_field}".to_owned(),
+                error_format: String::new(),
+                visibility: Default::default(),
+            }],
+        };
+        let data = |field: &str| {
+            format!(
+                r#"{{"elapsed_ms":10,"kind":"data","widget":"test/recorded_plugin","module_type":"Plugin","content":{{"Plugin":{{"field":"{field}","value":{{"Bool":true}},"status":null}}}}}}"#
+            )
+        };
+        let recording = [
+            serde_json::to_string(&Record {
+                elapsed_ms: 0,
+                content: layout,
+            })
+            .unwrap(),
+            data("not_recorded_field"),
+            data("recorded_field"),
+        ]
+        .join("\n");
+
+        let records = Recording::read(recording.as_bytes()).unwrap().records;
+
+        assert_eq!(records.len(), 2);
+        let RecordContent::Data { ref content, .. } = records[1].content else {
+            panic!("The data of the recorded field was skipped");
+        };
+        assert_eq!(content.field(), "recorded_field");
+        assert!(PluginField::get("not_recorded_field").is_none());
+    }
+}
@@ -1,10 +1,73 @@
-use crate::modules::{self, BackendModule, ModuleType, ModuleYield};
+use crate::cli;
+use crate::config_watcher::ConfigWatcher;
+use crate::control::ControlSocket;
+use crate::frontend;
+use crate::introspect::Stats;
+use crate::modules::{self, ModuleDataProvider, ModuleType, ModuleYield};
+use crate::modules::{
+    command::CommandConfig, plugin::PluginConfig, time::TimeConfig, upower::UpowerConfig,
+};
 use crate::prelude::*;
+use crate::recorder::{Recorder, Recording};
+use crate::shutdown::{Shutdown, ShutdownReason};
+use crate::supervisor::{RestartConfig, Supervisor};
+use crate::to_frontend::{
+    self, FrontendChannel, FrontendMux, FrontendSender, ModuleStatus, Visibility, WidgetLayout,
+    DEFAULT_ERROR_FORMAT,
+};
+use halobar_core::client::{DataRequest, Request};
 use tokio::runtime::Runtime;
+use tokio::sync::watch;
 
 #[inline]
-pub async fn run(runtime: Arc<Runtime>, config: ModuleConfig) -> R<()> {
-    let mut initializer = BackendInitializer::new(runtime.clone(), config).await?;
+pub async fn run(
+    runtime: Arc<Runtime>,
+    mut config: ModuleConfig,
+    config_path: Option<PathBuf>,
+    cli: cli::Cli,
+) -> R<ShutdownReason> {
+    let frontend_kind = cli.frontend.unwrap_or_default();
+    let mut mux = FrontendMux::new();
+
+    let shutdown = Shutdown::new();
+    runtime.spawn({
+        let shutdown = shutdown.clone();
+        async move {
+            if let Err(e) = shutdown.listen_for_signals().await {
+                error!("Failed to listen for signals: {e}");
+            }
+        }
+    });
+
+    if let Some(ref path) = cli.record {
+        mux.record_to(Recorder::create(path)?);
+    }
+
+    if let Some(ref path) = cli.replay {
+        let recording = Recording::open(path)?;
+        return replay(
+            runtime,
+            mux,
+            frontend_kind,
+            recording,
+            cli.replay_speed,
+            shutdown,
+        )
+        .await;
+    }
+
+    config.prepare_for(&frontend_kind);
+
+    let control = match ControlSocket::start(&runtime, shutdown.clone(), mux.stats()).await {
+        Ok(c) => Some(c),
+        Err(e) => {
+            warn!("Not serving the halogen socket: {e}");
+            None
+        }
+    };
+
+    let mut initializer =
+        BackendInitializer::new(runtime.clone(), config, mux.status_sender(), mux.stats()).await?;
 
     // Spawn each module on a task -- they start running instantly!
     let mut handles = initializer.run().await?;
@@ -12,123 +75,707 @@ pub async fn run(runtime: Arc<Runtime>, config: ModuleConfig) -> R<()> {
     // Get the yielded data from this function!
     let modules = initializer.receive_from_channels().await?;
 
-    // TODO: Connect to frontend
-    // let frontend_channel = crate::backend::Backend::init(modules)?;
+    mux.set_layout(initializer.layout.clone());
+    for (instance, yielded) in modules {
+        mux.add_module(instance, yielded);
+    }
 
-    // let _eavesdrop_handle =
-    //     runtime.spawn(async move { crate::backend::get_backend()?.eavesdrop().await });
+    let _frontend_handle = spawn_frontend(&runtime, &frontend_kind, mux.attacher().attach()?);
+    let _mux_handle = runtime.spawn(mux.run());
 
-    while let Some(res) = handles.next().await {
-        let (mod_name, module_return) = match res {
-            Ok(r) => r,
-            Err(e) => {
-                error!("Failed to join task: {e}");
-                continue;
+    let mut watcher = config_path.and_then(|path| match ConfigWatcher::new(path) {
+        Ok(w) => Some(w),
+        Err(e) => {
+            warn!("Failed to watch config file, hot reloading is disabled: {e}");
+            None
+        }
+    });
+
+    let reason = loop {
+        select! {
+            reason = shutdown.wait() => break reason,
+
+            Some(res) = handles.next() => {
+                let (instance, module_return) = match res {
+                    Ok(r) => r,
+                    // Modules are aborted when they are restarted or removed by a config reload
+                    Err(e) if e.is_cancelled() => {
+                        debug!("Module task was stopped");
+                        continue;
+                    }
+                    Err(e) => {
+                        error!("Failed to join task: {e}");
+                        continue;
+                    }
+                };
+
+                match module_return {
+                    Ok(()) => debug!("Module {} returned", instance),
+                    Err(e) => error!("Module {} returned error: {}", instance, e),
+                }
             }
-        };
 
-        match module_return {
-            Ok(true) => debug!("Module {} returned true", mod_name),
-            Ok(false) => warn!("Module {} returned incorrectly!", mod_name),
-            Err(e) => error!("Module {} returned error: {}", mod_name, e),
+            reloaded = next_config(&mut watcher), if watcher.is_some() => match reloaded {
+                Ok(mut config) => {
+                    config.prepare_for(&frontend_kind);
+                    if let Err(e) = initializer.reload(config, &mut handles) {
+                        error!("Failed to reload config: {e}");
+                    }
+                }
+                Err(e) => {
+                    error!("Config watcher failed, hot reloading is disabled: {e}");
+                    watcher = None;
+                }
+            },
         }
+    };
+
+    initializer.stop(&mut handles).await;
+    if let Some(control) = control {
+        control.stop();
     }
 
-    warn!("Finished backend execution!");
-    Ok(())
+    Ok(reason)
+}
+
+/// Replay a recording into the mux instead of running the modules.
+async fn replay(
+    runtime: Arc<Runtime>,
+    mux: FrontendMux,
+    frontend_kind: cli::Frontend,
+    mut recording: Recording,
+    speed: f64,
+    shutdown: Shutdown,
+) -> R<ShutdownReason> {
+    match recording.take_initial_layout() {
+        Some(layout) => mux.set_layout(layout),
+        None => {
+            warn!("Recording does not start with a layout, the frontend might ignore some data")
+        }
+    }
+
+    let message_sender = Arc::clone(&mux.message_sender);
+    let status_sender = mux.status_sender();
+
+    let _frontend_handle = spawn_frontend(&runtime, &frontend_kind, mux.attacher().attach()?);
+    let _mux_handle = runtime.spawn(mux.run());
+
+    let replaying = async {
+        recording
+            .replay(speed, message_sender, status_sender)
+            .await?;
+
+        // Keep the frontend showing the last state until the bar is closed
+        std::future::pending().await
+    };
+
+    select! {
+        reason = shutdown.wait() => Ok(reason),
+        result = replaying => result,
+    }
+}
+
+/// Spawn the chosen frontend on its own task
+fn spawn_frontend(
+    runtime: &Runtime,
+    frontend_kind: &cli::Frontend,
+    frontend: FrontendChannel,
+) -> tokio::task::JoinHandle<()> {
+    match frontend_kind {
+        cli::Frontend::Log => runtime.spawn(to_frontend::eavesdrop(frontend)),
+        cli::Frontend::I3bar => runtime.spawn(async move {
+            if let Err(e) = frontend::i3bar::run(frontend).await {
+                error!("i3bar frontend stopped: {e}");
+            }
+        }),
+        cli::Frontend::Waybar { .. } => runtime.spawn(async move {
+            if let Err(e) = frontend::waybar::run(frontend).await {
+                error!("waybar frontend stopped: {e}");
+            }
+        }),
+    }
+}
+
+/// Wait for the next config from the watcher, if there is one.
+async fn next_config(watcher: &mut Option<ConfigWatcher>) -> R<ModuleConfig> {
+    match watcher {
+        Some(w) => w.next().await,
+        None => std::future::pending().await,
+    }
 }
 
 const DEFAULT_START_TIMEOUT_SECONDS: u64 = 5;
+const DEFAULT_BAR_NAME: &str = "halobar";
+
+/// Get the default path of the config file, `$XDG_CONFIG_HOME/halobar/config.toml`
+pub fn config_path() -> Option<PathBuf> {
+    let mut path = halobar_config::xdg_config_home()?;
+    path.push("halobar");
+    path.push("config.toml");
+    Some(path)
+}
 
 /// The main module config.
-///
-/// TODO: Implement multi-instance modules
 #[derive(Debug, SmartDefault, Serialize, Deserialize)]
+#[serde(default)]
 pub struct ModuleConfig {
+    /// The name of this bar. Every widget's [`ModuleId`] starts with it, so it has to be unique when running several bars.
+    #[default(DEFAULT_BAR_NAME.into())]
+    pub bar: String,
     #[default(Some(DEFAULT_START_TIMEOUT_SECONDS))]
     pub start_timeout_seconds: Option<u64>,
-    pub time: modules::time::TimeConfig,
+    /// The config of the default `time` instance
+    pub time: TimeConfig,
+    /// The config of the default `upower` instance
+    pub upower: UpowerConfig,
+    /// How modules are restarted when they fail
+    pub restart: RestartConfig,
+    /// Named module instances, like `[module.clock_utc]`. Each one runs its own data provider.
+    pub module: AHashMap<InstanceName, InstanceConfig>,
+    /// The widgets on the bar, in order. Each widget requests data from a single module.
+    pub widgets: Vec<WidgetConfig>,
+    /// The format that widgets show when their module fails to get their data, unless they set their own.
+    #[default(DEFAULT_ERROR_FORMAT.into())]
+    pub error_format: String,
+}
+impl ModuleConfig {
+    /// Adjust the config for the frontend that is going to display it.
+    ///
+    /// Waybar only shows a single widget, so all the others are removed, and their modules are not started.
+    pub fn prepare_for(&mut self, frontend: &cli::Frontend) {
+        let cli::Frontend::Waybar { module, format } = frontend else {
+            return;
+        };
+
+        let widget = self.widgets.iter().find(|w| &w.module == module);
+        let format = match format {
+            Some(f) => Some(f.clone()),
+            None => widget.map(|w| w.format.clone()),
+        };
+        let visibility = widget.map(|w| w.visibility.clone()).unwrap_or_default();
+
+        self.widgets = match format {
+            Some(format) => vec![WidgetConfig {
+                module: module.clone(),
+                format,
+                name: None,
+                error_format: None,
+                visibility,
+            }],
+            None => {
+                error!("No widget uses module '{module}', and no format was given");
+                Vec::new()
+            }
+        };
+    }
+
+    /// Get the config of the module instance with this name.
+    ///
+    /// Each module type has a default instance named after it, so a widget can use `module = "time"`
+    /// without declaring it. This uses the top-level config of that type, like `[time]`.
+    /// Plugins and commands have to be declared, because they need a command.
+    pub fn instance(&self, name: &InstanceName) -> Option<InstanceConfig> {
+        if let Some(instance) = self.module.get(name) {
+            return Some(instance.clone());
+        }
+
+        let instance = match ModuleType::from_str(name.as_str()).ok()? {
+            ModuleType::Time => InstanceConfig::Time(self.time.clone()),
+            ModuleType::Upower => InstanceConfig::Upower(self.upower.clone()),
+            ModuleType::Plugin | ModuleType::Command => return None,
+        };
+        Some(instance)
+    }
+}
+
+/// The config of a single module instance. The `type` key determines the type of module.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum InstanceConfig {
+    Time(TimeConfig),
+    Upower(UpowerConfig),
+    Plugin(PluginConfig),
+    // Boxed because its nested restart config makes it much bigger than the others
+    Command(Box<CommandConfig>),
+}
+impl InstanceConfig {
+    /// Get the type of module that this instance runs
+    pub fn module_type(&self) -> ModuleType {
+        match self {
+            Self::Time(_) => ModuleType::Time,
+            Self::Upower(_) => ModuleType::Upower,
+            Self::Plugin(_) => ModuleType::Plugin,
+            Self::Command(_) => ModuleType::Command,
+        }
+    }
+}
+
+/// A single widget on the bar.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct WidgetConfig {
+    /// The name of the module instance that provides data for this widget
+    pub module: InstanceName,
+    /// The format string. The module determines which fields it has to provide from this.
+    pub format: String,
+    /// The name of this widget, which is part of its [`ModuleId`].
+    ///
+    /// This only matters if several widgets use the same module instance. If they are not named,
+    /// they are numbered in the order they appear, which changes when a widget is added in between.
+    #[serde(default)]
+    pub name: Option<String>,
+    /// The format to show when the module fails to get this widget's data. This overrides the bar's `error_format`.
+    #[serde(default)]
+    pub error_format: Option<String>,
+    /// When the widget is shown, like `show_if = [{ field = "keyboard_brightness" }]`
+    #[serde(default, flatten)]
+    pub visibility: Visibility,
+}
+
+/// The requests that widgets made to a single module instance
+struct InstanceRequests {
+    config: InstanceConfig,
+    requests: Vec<DataRequest>,
+}
+
+/// The join handle of a single module task
+type ModuleHandle = tokio::task::JoinHandle<(InstanceName, R<()>)>;
+/// The type of the join handles of each module task
+type ModuleHandles = FuturesUnordered<ModuleHandle>;
+
+/// A module task that was spawned, along with what it was spawned with.
+struct RunningModule {
+    config: InstanceConfig,
+    /// The module is started with these requests when it restarts.
+    /// They change when widgets subscribe to different fields at runtime.
+    requests: watch::Sender<Vec<DataRequest>>,
+    abort_handle: tokio::task::AbortHandle,
 }
 
 struct BackendInitializer {
     runtime: Arc<Runtime>,
-    receiver: mpsc::UnboundedReceiver<ModuleYield>,
-    sender: Option<Arc<mpsc::UnboundedSender<ModuleYield>>>,
-    /// I need this to be a hashmap because the listeners do not return in an ordered manner.
-    expected_modules: AHashMap<ModuleId, ModuleType>,
+    /// I need a receiver per module because [`ModuleYield`] does not say where it came from.
+    receivers: Vec<(InstanceName, mpsc::UnboundedReceiver<ModuleYield>)>,
+    /// The modules that are running, so they can be restarted when the config changes.
+    running: AHashMap<InstanceName, RunningModule>,
+    /// The widgets on the bar, in order
+    layout: Vec<WidgetLayout>,
+    status_sender: FrontendSender<ModuleStatus>,
+    /// The statistics of each module instance, which are registered here when they are spawned
+    stats: Stats,
     config: ModuleConfig,
 }
 impl BackendInitializer {
     /// Internal backend initializer creation function
-    pub async fn new(runtime: Arc<Runtime>, config: ModuleConfig) -> R<Self> {
-        // Each module must send a listener to this channel when they are ready to push data.
-        // I made it this way because rust doesn't have generator functions, and I needed a way for functions to yield values
-        // when I need them, and for them to just run as single instances. A bunch of dbus proxy lifetime stuff is involved there too.
-        // It would have been messy to make a Module::new() and Module::run() thing when the run method would have just errored out instantly.
-        let (sender, receiver) = mpsc::unbounded_channel();
-
+    pub async fn new(
+        runtime: Arc<Runtime>,
+        config: ModuleConfig,
+        status_sender: FrontendSender<ModuleStatus>,
+        stats: Stats,
+    ) -> R<Self> {
         Ok(Self {
             runtime,
-            receiver,
-            sender: Some(Arc::new(sender)),
-            expected_modules: AHashMap::new(),
+            receivers: Vec::new(),
+            running: AHashMap::new(),
+            layout: Vec::new(),
+            status_sender,
+            stats,
             config,
         })
     }
 
-    /// The second component of the runtime. This initializes modules and runs them in tokio tasks.
+    /// Collect the data requests for each module instance from the widgets in the config.
+    ///
+    /// Each widget gets its own [`ModuleId`], derived from the bar name, its module instance, and its name or position.
+    /// This also returns the layout of the widgets for the frontend, which only contains the valid widgets.
+    fn collect_requests(&self) -> R<(AHashMap<InstanceName, InstanceRequests>, Vec<WidgetLayout>)> {
+        // The number of unnamed widgets of each instance so far
+        let mut unnamed: AHashMap<InstanceName, usize> = AHashMap::new();
+        let mut requests: AHashMap<InstanceName, InstanceRequests> = AHashMap::new();
+        let mut layout = Vec::with_capacity(self.config.widgets.len());
+
+        for widget in self.config.widgets.iter() {
+            let Some(instance) = self.config.instance(&widget.module) else {
+                error!(
+                    "Widget '{}' uses unknown module '{}'",
+                    widget.format, widget.module
+                );
+                continue;
+            };
+
+            let module_type = instance.module_type();
+            let mut fields = match modules::request_fields(&module_type, &widget.format) {
+                Ok(f) => f,
+                Err(e) => {
+                    error!(
+                        "Invalid format for {} widget '{}': {e}",
+                        widget.module, widget.format
+                    );
+                    continue;
+                }
+            };
+
+            // The rules might check fields that the format does not show
+            let rule_fields = widget
+                .visibility
+                .rules()
+                .map(|r| r.field.as_str())
+                .collect::<Vec<_>>();
+            match modules::rule_fields(&module_type, &rule_fields) {
+                Ok(rule_fields) => {
+                    for field in rule_fields {
+                        if !fields.contains(&field) {
+                            fields.push(field);
+                        }
+                    }
+                }
+                Err(e) => {
+                    error!(
+                        "Invalid visibility rule for {} widget '{}': {e}",
+                        widget.module, widget.format
+                    );
+                    continue;
+                }
+            }
+
+            let error_format = widget
+                .error_format
+                .clone()
+                .unwrap_or_else(|| self.config.error_format.clone());
+            if let Err(e) = FmtSegmentVec::new(&error_format) {
+                error!(
+                    "Invalid error format for {} widget '{}': '{error_format}': {e}",
+                    widget.module, widget.format
+                );
+                continue;
+            }
+
+            let id = match widget.name.as_deref() {
+                Some(name) => ModuleId::for_widget(&self.config.bar, &widget.module, Some(name))?,
+                None => {
+                    let index = unnamed.entry(widget.module.clone()).or_default();
+                    let discriminator = (*index > 0).then(|| index.to_string());
+                    *index += 1;
+
+                    ModuleId::for_widget(
+                        &self.config.bar,
+                        &widget.module,
+                        discriminator.as_deref(),
+                    )?
+                }
+            };
+
+            if layout.iter().any(|w: &WidgetLayout| w.id == id) {
+                error!(
+                    "Widget '{}' has the same id as another widget: {id}, give it a unique name",
+                    widget.format
+                );
+                continue;
+            }
+
+            trace!(
+                "Widget {id} requests {fields:?} from module {}",
+                widget.module
+            );
+
+            layout.push(WidgetLayout {
+                id: id.clone(),
+                instance: widget.module.clone(),
+                module_type,
+                format: widget.format.clone(),
+                error_format,
+                visibility: widget.visibility.clone(),
+            });
+
+            requests
+                .entry(widget.module.clone())
+                .or_insert_with(|| InstanceRequests {
+                    config: instance,
+                    requests: Vec::new(),
+                })
+                .requests
+                .push(DataRequest {
+                    id,
+                    data_fields: fields.into_iter().map(Request::Request).collect(),
+                });
+        }
+
+        Ok((requests, layout))
+    }
+
+    /// The second component of the runtime. This initializes modules and runs them in tokio tasks,
+    /// each under a [`Supervisor`] that restarts it if it fails.
+    ///
+    /// Modules that no widget requests data from are not started.
     ///
     /// It returns handles to each module's task.
     #[instrument(level = "trace", skip_all)]
-    pub async fn run(
-        &mut self,
-    ) -> R<FuturesUnordered<tokio::task::JoinHandle<(&'static str, Result<bool, Report>)>>> {
+    pub async fn run(&mut self) -> R<ModuleHandles> {
         let handles = FuturesUnordered::new();
-        let sender = self.sender.take().expect(
-            "Runtime functions out of order! Backend initializer is missing its internal sender!",
-        );
+        let (requests, layout) = self.collect_requests()?;
+        self.layout = layout;
 
-        let mut module_id_creator = ModuleIdFactory::new();
+        for (instance, instance_requests) in requests {
+            let (yield_sender, yield_receiver) = mpsc::unbounded_channel();
 
-        // TODO: Make this a trait that all module configs must impl
-        macro_rules! init_module {
-            ($( [$mod_name:expr] module: $mod_path:ty, input: $input:expr ),+$(,)?) => {$({
-                let yield_sender = Arc::clone(&sender);
-                let input = $input;
-                const ERR_EXPECT: &str = ::const_format::concatcp!("Failed to create a module ID for module", stringify!($mod_name));
-                let id = module_id_creator.generate().expect(ERR_EXPECT);
+            trace!("Initializing module {instance}");
+            self.receivers.push((instance.clone(), yield_receiver));
 
-                trace!("Initializing module {}:{}", $mod_name, id);
-                self.expected_modules.insert(id.clone(), <$mod_path>::MODULE_TYPE);
+            handles.push(self.spawn(instance, instance_requests, Some(yield_sender)));
+        }
 
-                let handle = self.runtime.spawn(async move {
-                    let module_return = <$mod_path>::run(id, input, yield_sender).await;
-                    ($mod_name, module_return)
-                });
+        Ok(handles)
+    }
+
+    /// Apply a new config, restarting only the modules whose config changed.
+    ///
+    /// Modules that are unchanged keep running, and their widgets keep their subscriptions.
+    /// If only the fields that the widgets request changed, the widgets subscribe to them at runtime instead.
+    /// Modules that no widget requests data from anymore are stopped.
+    #[instrument(level = "trace", skip_all)]
+    pub fn reload(&mut self, config: ModuleConfig, handles: &mut ModuleHandles) -> R<()> {
+        let old_config = std::mem::replace(&mut self.config, config);
 
-                handles.push(handle);
-            })+};
+        let (requests, layout) = match self.collect_requests() {
+            Ok(r) => r,
+            Err(e) => {
+                self.config = old_config;
+                // The widgets of the rejected config might have been registered already
+                ModuleId::retain(self.layout.iter().map(|w| &w.id));
+                return Err(e);
+            }
+        };
+
+        // Renamed and removed widgets would otherwise stay registered for as long as the bar runs
+        ModuleId::retain(layout.iter().map(|w| &w.id));
+
+        // This has to go out before any of the restarted modules yield, so the frontend knows about their widgets.
+        if layout != self.layout {
+            self.layout = layout;
+            self.send_status(ModuleStatus::Layout(self.layout.clone()));
         }
 
-        init_module! {
-            ["time"]
-            module: crate::modules::time::Time,
-            input: self.config.time.clone(),
+        // Every supervisor was created with the restart config
+        let restart_changed = old_config.restart != self.config.restart;
+
+        let removed = self
+            .running
+            .keys()
+            .filter(|instance| !requests.contains_key(instance))
+            .cloned()
+            .collect::<Vec<_>>();
+
+        for instance in removed {
+            info!("Stopping module {instance}, no widgets request its data anymore");
+            if let Some(module) = self.running.remove(&instance) {
+                module.abort_handle.abort();
+            }
+            self.stats.remove(&instance);
+            self.send_status(ModuleStatus::Removed(instance));
         }
 
-        Ok(handles)
+        for (instance, instance_requests) in requests {
+            let config_unchanged = !restart_changed
+                && self
+                    .running
+                    .get(&instance)
+                    .is_some_and(|m| m.config == instance_requests.config);
+
+            if config_unchanged {
+                self.resubscribe(&instance, instance_requests.requests);
+                continue;
+            }
+
+            match self.running.remove(&instance) {
+                Some(module) => {
+                    info!("Restarting module {instance} with the new config");
+                    module.abort_handle.abort();
+                }
+                None => info!("Starting module {instance}"),
+            }
+
+            // Nobody is waiting on the initial yield here, so it goes straight to the mux.
+            handles.push(self.spawn(instance, instance_requests, None));
+        }
+
+        Ok(())
     }
 
-    /// The third component of initialization.
+    /// Subscribe and unsubscribe the fields that the widgets of a running module instance
+    /// started or stopped requesting, so it does not have to restart.
+    fn resubscribe(&self, instance: &InstanceName, requests: Vec<DataRequest>) {
+        let Some(module) = self.running.get(instance) else {
+            return;
+        };
+
+        let mut statuses = Vec::new();
+        module.requests.send_if_modified(|old| {
+            if *old == requests {
+                trace!("Module {instance} is unchanged");
+                return false;
+            }
+
+            let fields_of = |requests: &[DataRequest], widget: &ModuleId| {
+                requests
+                    .iter()
+                    .find(|r| r.id == *widget)
+                    .map(|r| r.data_fields.clone())
+                    .unwrap_or_default()
+            };
+
+            // Subscribe first, so that streams the widgets still share are not stopped and restarted.
+            for request in requests.iter() {
+                let old_fields = fields_of(old, &request.id);
+                let added = request
+                    .data_fields
+                    .iter()
+                    .filter(|f| !old_fields.contains(f))
+                    .cloned()
+                    .collect::<Vec<_>>();
+
+                if !added.is_empty() {
+                    statuses.push(ModuleStatus::Subscribe {
+                        instance: instance.clone(),
+                        request: DataRequest {
+                            id: request.id.clone(),
+                            data_fields: added,
+                        },
+                    });
+                }
+            }
+
+            for request in old.iter() {
+                let new_fields = fields_of(&requests, &request.id);
+                let removed = request
+                    .data_fields
+                    .iter()
+                    .filter(|f| !new_fields.contains(f))
+                    .filter_map(|f| match f {
+                        Request::Request(field) => Some(field.clone()),
+                        _ => None,
+                    })
+                    .collect::<Vec<_>>();
+
+                if !removed.is_empty() {
+                    statuses.push(ModuleStatus::Unsubscribe {
+                        instance: instance.clone(),
+                        widget: request.id.clone(),
+                        fields: removed,
+                    });
+                }
+            }
+
+            info!("Updating the subscriptions of module {instance}");
+            *old = requests;
+            true
+        });
+
+        for status in statuses {
+            self.send_status(status);
+        }
+    }
+
+    /// Spawn a module instance on its own task under a [`Supervisor`], keeping track of it.
     ///
-    /// This waits for each module to return a listener for its value, makes sure everything is alright with return types and whatnot,
-    /// then returns the raw, yielded data.
-    pub async fn receive_from_channels(&mut self) -> R<Vec<ModuleYield>> {
-        #[cfg(debug_assertions)]
-        if self.sender.is_some() {
-            unreachable!("Runtime functions out of order! Backend initializer has not dropped its internal sender!");
+    /// If `initial_yield` is `None`, the module's first yield goes to the [`FrontendMux`], like a restart would.
+    fn spawn(
+        &mut self,
+        instance: InstanceName,
+        instance_requests: InstanceRequests,
+        initial_yield: Option<mpsc::UnboundedSender<ModuleYield>>,
+    ) -> ModuleHandle {
+        let InstanceRequests { config, requests } = instance_requests;
+        let (requests, module_requests) = watch::channel(requests);
+
+        self.stats
+            .register(instance.clone(), config.module_type(), &config);
+
+        let supervisor = Supervisor::new(
+            instance.clone(),
+            self.config.restart.into_known(),
+            module_requests.clone(),
+            Arc::clone(&self.status_sender),
+        );
+        let task_instance = instance.clone();
+
+        // The future returned by ModuleDataProvider::main is not known to be Send in a generic context,
+        // so this has to be a macro that uses the concrete types.
+        macro_rules! spawn_module {
+            ($( [$mod_type:ident] module: $mod_path:ty ),+$(,)?) => {
+                match config.clone() {$(
+                    InstanceConfig::$mod_type(module_config) => {
+                        // Some configs are boxed, so this gets the config itself
+                        let module_config: &<$mod_path as ModuleDataProvider>::ServerConfig = &module_config;
+                        let module_config = module_config.clone();
+                        let throttle = module_config.throttle.into_known();
+                        let throttle_instance = instance.clone();
+                        self.runtime.spawn(async move {
+                            let module_return = supervisor
+                                .supervise(initial_yield, move |yield_sender| {
+                                    let yield_sender = throttle.yield_sender(throttle_instance.clone(), yield_sender);
+                                    <$mod_path>::main(module_config.clone(), module_requests.borrow().clone(), yield_sender)
+                                })
+                                .await;
+                            (task_instance, module_return)
+                        })
+                    }
+                )+}
+            };
         }
 
+        let handle = spawn_module! {
+            [Time]
+            module: modules::time::Time,
+            [Upower]
+            module: modules::upower::UpowerMod,
+            [Plugin]
+            module: modules::plugin::PluginModule,
+            [Command]
+            module: modules::command::CommandModule,
+        };
+
+        self.running.insert(
+            instance,
+            RunningModule {
+                config,
+                requests,
+                abort_handle: handle.abort_handle(),
+            },
+        );
+
+        handle
+    }
+
+    /// Stop every module, waiting a moment for their tasks to finish,
+    /// so that they drop everything they own, like the child processes of commands.
+    pub async fn stop(&mut self, handles: &mut ModuleHandles) {
+        const STOP_TIMEOUT: Duration = Duration::from_secs(2);
+
+        for (instance, module) in self.running.drain() {
+            debug!("Stopping module {instance}");
+            module.abort_handle.abort();
+        }
+
+        let stopped = tokio::time::timeout(STOP_TIMEOUT, async {
+            while handles.next().await.is_some() {}
+        })
+        .await;
+
+        if stopped.is_err() {
+            warn!("Some modules did not stop in time");
+        }
+    }
+
+    fn send_status(&self, status: ModuleStatus) {
+        if self.status_sender.send(status).is_err() {
+            warn!("Failed to send module status to the mux");
+        }
+    }
+
+    /// The third component of initialization.
+    ///
+    /// This waits for each module to return a listener for its value,
+    /// then returns the raw, yielded data along with the type of module that yielded it.
+    pub async fn receive_from_channels(&mut self) -> R<Vec<(InstanceName, ModuleYield)>> {
         const SECOND: Duration = Duration::from_secs(1);
 
         let timeout = self
@@ -136,36 +783,32 @@ impl BackendInitializer {
             .start_timeout_seconds
             .unwrap_or(DEFAULT_START_TIMEOUT_SECONDS);
 
-        // let mut last_recv = Mutex::new(tokio::time::Instant::now());
         let last_recv = Cell::new(tokio::time::Instant::now());
         let mut results = Vec::new();
 
+        let mut pending = self
+            .receivers
+            .drain(..)
+            .map(|(instance, mut receiver)| async move {
+                let yielded = receiver.recv().await;
+                (instance, yielded)
+            })
+            .collect::<FuturesUnordered<_>>();
+
         let listener_future = async {
-            while let Some(yielded) = self.receiver.recv().await {
+            while let Some((instance, yielded)) = pending.next().await {
                 // we have to refresh the counter or it may close us unexpectedly!
                 last_recv.replace(tokio::time::Instant::now());
 
-                // this runtime checking
-                match self.expected_modules.remove(&yielded.id) {
-                    Some(expected_type) => debug!("Received module with id {}: Expected module type {}, received module type {}", yielded.id, expected_type, yielded.module_type),
-                    None => bail!("Module with id of {} has no module type!", yielded.id),
-                }
-
-                results.push(yielded);
-
-                if self.expected_modules.is_empty() {
-                    break;
+                match yielded {
+                    Some(y) => {
+                        debug!("Received yield from module {instance}");
+                        results.push((instance, y));
+                    }
+                    // The module dropped its sender, which means it returned before yielding.
+                    None => warn!("Module {instance} failed to yield"),
                 }
             }
-
-            if !self.expected_modules.is_empty() {
-                for (id, mod_type) in self.expected_modules.iter() {
-                    warn!("Failed to yield module with id of {id}, module type of {mod_type}");
-                }
-                bail!("Some modules failed to yield!");
-            }
-
-            Ok(())
         };
 
         // This will only return if the timeout is hit.
@@ -189,9 +832,7 @@ impl BackendInitializer {
             timeout = timeout_future => {
                 timeout?;
             }
-            listened = listener_future => {
-                listened?;
-            }
+            () = listener_future => {}
         }
 
         Ok(results)
@@ -0,0 +1,149 @@
+//! Shuts the bar down gracefully, when it receives a signal or is asked to over halogen.
+
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::watch;
+
+use crate::prelude::*;
+
+/// Why the bar is shutting down
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ShutdownReason {
+    /// The process received a signal
+    Signal { name: &'static str, number: i32 },
+    /// A halogen client asked the bar to shut down
+    Requested,
+}
+impl ShutdownReason {
+    /// Get the code that the process should exit with.
+    ///
+    /// Signals use the shell convention of 128 plus the signal number, so scripts can tell what happened.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Self::Signal { number, .. } => 128 + number,
+            Self::Requested => 0,
+        }
+    }
+}
+impl std::fmt::Display for ShutdownReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Signal { name, .. } => write!(f, "received {name}"),
+            Self::Requested => f.write_str("requested over halogen"),
+        }
+    }
+}
+
+/// A handle to trigger or wait for the shutdown. It is cheap to clone.
+#[derive(Debug, Clone)]
+pub struct Shutdown {
+    sender: Arc<watch::Sender<Option<ShutdownReason>>>,
+}
+impl Default for Shutdown {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+impl Shutdown {
+    pub fn new() -> Self {
+        let (sender, _) = watch::channel(None);
+        Self {
+            sender: Arc::new(sender),
+        }
+    }
+
+    /// Start shutting down. If it is already shutting down, the first reason is kept.
+    pub fn trigger(&self, reason: ShutdownReason) {
+        self.sender.send_if_modified(|current| {
+            if current.is_some() {
+                debug!("Already shutting down, ignoring shutdown that was {reason}");
+                return false;
+            }
+
+            info!("Shutting down, {reason}");
+            *current = Some(reason);
+            true
+        });
+    }
+
+    /// Wait until the shutdown is triggered. This returns instantly if it already was.
+    pub async fn wait(&self) -> ShutdownReason {
+        let mut receiver = self.sender.subscribe();
+
+        // The sender lives in self, so this can't fail.
+        let reason = *receiver
+            .wait_for(Option::is_some)
+            .await
+            .expect("Shutdown sender was dropped while waiting");
+
+        reason.expect("Shutdown reason was checked by wait_for")
+    }
+
+    /// Trigger the shutdown when the process receives SIGINT, SIGTERM or SIGQUIT.
+    ///
+    /// Tokio never restores the default signal handlers, so a second signal exits right away,
+    /// in case the graceful shutdown gets stuck.
+    pub async fn listen_for_signals(self) -> R<()> {
+        macro_rules! signals {
+            ($( $sigtype:ident => $name:literal ),+$(,)?) => {
+                [$( (SignalKind::$sigtype(), $name) ),+]
+            };
+        }
+
+        let mut signals =
+            signals![interrupt => "SIGINT", terminate => "SIGTERM", quit => "SIGQUIT"]
+                .into_iter()
+                .map(|(kind, name)| Ok((signal(kind)?, name, kind.as_raw_value())))
+                .collect::<R<Vec<_>>>()?;
+
+        loop {
+            let received = signals
+                .iter_mut()
+                .map(|(receiver, name, number)| async move {
+                    receiver.recv().await?;
+                    Some(ShutdownReason::Signal {
+                        name,
+                        number: *number,
+                    })
+                })
+                .collect::<FuturesUnordered<_>>()
+                .next()
+                .await
+                .flatten();
+
+            let Some(reason) = received else {
+                bail!("Stopped receiving signals");
+            };
+
+            let current = *self.sender.borrow();
+            match current {
+                Some(first) => {
+                    warn!("Received another signal while shutting down, exiting now");
+                    halogen::complete::flush_log();
+                    std::process::exit(first.exit_code());
+                }
+                None => self.trigger(reason),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn first_reason_wins() {
+        let shutdown = Shutdown::new();
+        let sigterm = ShutdownReason::Signal {
+            name: "SIGTERM",
+            number: 15,
+        };
+
+        shutdown.trigger(sigterm);
+        shutdown.trigger(ShutdownReason::Requested);
+
+        let reason = shutdown.clone().wait().await;
+        assert_eq!(reason, sigterm);
+        assert_eq!(reason.exit_code(), 143);
+    }
+}
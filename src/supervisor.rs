@@ -0,0 +1,313 @@
+//! Keeps data provider tasks alive, restarting them when they fail.
+
+use std::future::Future;
+
+use crate::modules::ModuleYield;
+use crate::prelude::*;
+use crate::to_frontend::{FrontendSender, ModuleStatus};
+use halobar_core::client::{DataRequest, ErrorChain, ProviderError};
+use tokio::sync::watch;
+
+config_struct! {
+    @known {Clone, Copy}
+    @config {Clone, Copy, PartialEq}
+    [Restart]
+    policy: RestartPolicy = RestartPolicy::OnFailure,
+    // How long to wait before the first restart. This doubles after each consecutive failure.
+    initial_backoff_ms: u64 = 500,
+    // The maximum amount of time to wait between restarts.
+    max_backoff_ms: u64 = 60_000,
+    // The maximum number of consecutive restarts before giving up. 0 means it will never give up.
+    max_restarts: u32 = 0,
+}
+
+/// When a module should be restarted
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum RestartPolicy {
+    /// Never restart the module
+    Never,
+    /// Restart the module only if it returned an error
+    #[default]
+    OnFailure,
+    /// Restart the module whenever it returns, even if it returned successfully
+    Always,
+}
+impl RestartPolicy {
+    /// Determine if a module that returned this result should be restarted
+    pub fn should_restart<T>(&self, result: &R<T>) -> bool {
+        match self {
+            Self::Never => false,
+            Self::OnFailure => result.is_err(),
+            Self::Always => true,
+        }
+    }
+}
+
+/// The delays between consecutive restarts, which double after each restart, up to the maximum.
+#[derive(Debug)]
+pub struct Backoff {
+    initial: Duration,
+    max: Duration,
+    next: Duration,
+    restarts: u32,
+    max_restarts: u32,
+}
+impl Backoff {
+    pub fn new(config: &RestartKnown) -> Self {
+        let initial = Duration::from_millis(config.initial_backoff_ms);
+        Self {
+            initial,
+            max: Duration::from_millis(config.max_backoff_ms),
+            next: initial,
+            restarts: 0,
+            max_restarts: config.max_restarts,
+        }
+    }
+
+    /// The number of restarts so far
+    #[inline]
+    pub fn restarts(&self) -> u32 {
+        self.restarts
+    }
+
+    /// Check if the maximum number of restarts was reached
+    #[inline]
+    pub fn is_exhausted(&self) -> bool {
+        self.max_restarts != 0 && self.restarts >= self.max_restarts
+    }
+
+    /// Count a restart, returning how long to wait before it.
+    ///
+    /// If the last run lasted for a while, then it is not failing consecutively,
+    /// so this starts over at the initial delay and stops counting the restarts before it.
+    pub fn next_delay(&mut self, ran_for: Duration) -> Duration {
+        if ran_for >= self.max {
+            self.next = self.initial;
+            self.restarts = 0;
+        }
+
+        let delay = self.next;
+        self.next = self.next.saturating_mul(2).min(self.max);
+        self.restarts += 1;
+        delay
+    }
+}
+
+/// A supervisor for a single data provider task.
+pub struct Supervisor {
+    instance: InstanceName,
+    config: RestartKnown,
+    /// The requests of the widgets that this module provides data for, so they can be marked as errored.
+    requests: watch::Receiver<Vec<DataRequest>>,
+    status_sender: FrontendSender<ModuleStatus>,
+}
+impl Supervisor {
+    pub fn new(
+        instance: InstanceName,
+        config: RestartKnown,
+        requests: watch::Receiver<Vec<DataRequest>>,
+        status_sender: FrontendSender<ModuleStatus>,
+    ) -> Self {
+        Self {
+            instance,
+            config,
+            requests,
+            status_sender,
+        }
+    }
+
+    /// Run the module until the restart policy says it should stop.
+    ///
+    /// `run` must start the module with a fresh copy of its config and its current [`DataRequest`]s.
+    ///
+    /// The first run yields to `initial_yield`, so the runtime can gather it at startup.
+    /// Every run after that yields to the [`crate::to_frontend::FrontendMux`], which swaps in the new subscription.
+    /// If there is no `initial_yield`, like when the config is reloaded, every run yields to the mux.
+    pub async fn supervise<F, Fut>(
+        self,
+        mut initial_yield: Option<mpsc::UnboundedSender<ModuleYield>>,
+        mut run: F,
+    ) -> R<()>
+    where
+        F: FnMut(mpsc::UnboundedSender<ModuleYield>) -> Fut,
+        Fut: Future<Output = R<()>>,
+    {
+        let mut backoff = Backoff::new(&self.config);
+
+        loop {
+            let started = Instant::now();
+
+            let result = match initial_yield.take() {
+                Some(yield_sender) => run(yield_sender).await,
+                None => self.run_forwarding(&mut run).await,
+            };
+
+            if let Err(ref e) = result {
+                error!("Module {} returned error: {e}", self.instance);
+                self.set_stopped(ProviderError::Stopped {
+                    source: ErrorChain::new(e.as_ref()),
+                });
+            }
+
+            if !self.config.policy.should_restart(&result) {
+                return result;
+            }
+
+            if backoff.is_exhausted() {
+                error!(
+                    "Module {} reached its maximum of {} restarts, giving up",
+                    self.instance,
+                    backoff.restarts()
+                );
+                return result;
+            }
+
+            let delay = backoff.next_delay(started.elapsed());
+            warn!(
+                "Restarting module {} in {}ms",
+                self.instance,
+                delay.as_millis()
+            );
+            if self
+                .status_sender
+                .send(ModuleStatus::Restarting(self.instance.clone()))
+                .is_err()
+            {
+                warn!("Failed to send restart status of module {}", self.instance);
+            }
+            tokio::time::sleep(delay).await;
+        }
+    }
+
+    /// Run the module, forwarding everything it yields to the mux.
+    async fn run_forwarding<F, Fut>(&self, run: &mut F) -> R<()>
+    where
+        F: FnMut(mpsc::UnboundedSender<ModuleYield>) -> Fut,
+        Fut: Future<Output = R<()>>,
+    {
+        let (yield_sender, mut yield_receiver) = mpsc::unbounded_channel();
+
+        let module_future = run(yield_sender);
+        tokio::pin!(module_future);
+
+        let result = loop {
+            select! {
+                result = &mut module_future => break result,
+                Some(yielded) = yield_receiver.recv() => self.forward(yielded),
+            }
+        };
+
+        // It might have yielded right before it returned
+        while let Ok(yielded) = yield_receiver.try_recv() {
+            self.forward(yielded);
+        }
+
+        result
+    }
+
+    fn forward(&self, yielded: ModuleYield) {
+        debug!("Module {} yielded after restarting", self.instance);
+
+        let status = ModuleStatus::Yielded(self.instance.clone(), yielded);
+        if self.status_sender.send(status).is_err() {
+            warn!("Failed to forward yield from module {}", self.instance);
+        }
+    }
+
+    /// Mark all of this module's widgets as errored
+    fn set_stopped(&self, error: ProviderError) {
+        let status = ModuleStatus::Stopped {
+            instance: self.instance.clone(),
+            widgets: self
+                .requests
+                .borrow()
+                .iter()
+                .map(|r| r.id.clone())
+                .collect(),
+            error,
+        };
+        if self.status_sender.send(status).is_err() {
+            warn!("Failed to send stop status of module {}", self.instance);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::to_frontend::FrontendMux;
+
+    #[tokio::test(start_paused = true)]
+    async fn restarts_until_limit() {
+        let mux = FrontendMux::new();
+        let config = RestartKnown {
+            max_restarts: 2,
+            ..Default::default()
+        };
+        let supervisor = Supervisor::new(
+            "time".into(),
+            config,
+            watch::channel(Vec::new()).1,
+            mux.status_sender(),
+        );
+
+        let runs = Cell::new(0);
+        let (initial_yield, _initial_receiver) = mpsc::unbounded_channel();
+        let result = supervisor
+            .supervise(Some(initial_yield), |_| {
+                runs.set(runs.get() + 1);
+                async { Err::<(), _>(eyre!("Failed")) }
+            })
+            .await;
+
+        assert!(result.is_err());
+        assert_eq!(runs.get(), 3);
+    }
+
+    #[test]
+    fn backoff_starts_over_after_long_run() {
+        let config = RestartKnown {
+            initial_backoff_ms: 100,
+            max_backoff_ms: 1000,
+            max_restarts: 3,
+            ..Default::default()
+        };
+        let mut backoff = Backoff::new(&config);
+        let quick = Duration::ZERO;
+
+        let delays: Vec<_> = (0..3).map(|_| backoff.next_delay(quick)).collect();
+        assert_eq!(delays, [100, 200, 400].map(Duration::from_millis));
+        assert!(backoff.is_exhausted());
+
+        assert_eq!(
+            backoff.next_delay(Duration::from_secs(1)),
+            Duration::from_millis(100)
+        );
+        assert_eq!(backoff.restarts(), 1);
+        assert!(!backoff.is_exhausted());
+        assert_eq!(backoff.next_delay(quick), Duration::from_millis(200));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn never_restarts_success() {
+        let mux = FrontendMux::new();
+        let supervisor = Supervisor::new(
+            "time".into(),
+            RestartKnown::default(),
+            watch::channel(Vec::new()).1,
+            mux.status_sender(),
+        );
+
+        let runs = Cell::new(0);
+        let (initial_yield, _initial_receiver) = mpsc::unbounded_channel();
+        let result = supervisor
+            .supervise(Some(initial_yield), |_| {
+                runs.set(runs.get() + 1);
+                async { Ok(()) }
+            })
+            .await;
+
+        assert!(result.is_ok());
+        assert_eq!(runs.get(), 1);
+    }
+}
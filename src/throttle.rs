@@ -0,0 +1,271 @@
+//! Limits how often a module's data reaches the frontends, so noisy modules can't monopolize redraws.
+
+use crate::modules::{ModuleOutput, ModuleYield};
+use crate::prelude::*;
+
+config_struct! {
+    @known {Clone, Copy, PartialEq, Eq}
+    @config {Clone, Copy, PartialEq, Eq}
+    [Throttle]
+    // The minimum amount of time between two updates of the module's widgets. 0 disables it.
+    min_interval_ms: u64 = 0,
+    // Wait until the module has not sent anything for this long before updating its widgets. 0 disables it.
+    // A module that never stops sending still updates them at least this often, or every `min_interval_ms` if that is longer.
+    debounce_ms: u64 = 0,
+}
+impl ThrottleKnown {
+    /// Check if this throttles anything at all
+    #[inline]
+    pub fn is_enabled(&self) -> bool {
+        self.min_interval_ms != 0 || self.debounce_ms != 0
+    }
+
+    /// Get a sender that throttles the subscription of everything that is yielded to it, forwarding it to `sender`.
+    ///
+    /// The initial data that a module yields is not throttled, only the updates after that.
+    pub fn yield_sender(
+        self,
+        instance: InstanceName,
+        sender: mpsc::UnboundedSender<ModuleYield>,
+    ) -> mpsc::UnboundedSender<ModuleYield> {
+        if !self.is_enabled() {
+            return sender;
+        }
+
+        let (throttled_sender, mut receiver) = mpsc::unbounded_channel::<ModuleYield>();
+
+        // This ends when the module drops its sender, which it does when it returns or is aborted.
+        tokio::spawn(async move {
+            while let Some(mut yielded) = receiver.recv().await {
+                if let Some(channel) = yielded.subscription.take() {
+                    let (output, throttled) = flume::unbounded();
+                    tokio::spawn(self.run(instance.clone(), channel.receiver, output));
+
+                    yielded.subscription = Some(BiChannel {
+                        sender: channel.sender,
+                        receiver: throttled,
+                    });
+                }
+
+                if sender.send(yielded).is_err() {
+                    warn!("Failed to forward throttled yield from module {instance}");
+                }
+            }
+        });
+
+        throttled_sender
+    }
+
+    /// Forward the module's output, coalescing everything it sends between two updates.
+    ///
+    /// Only the latest value of each field of each widget is kept, in the order they were last sent.
+    /// Nothing waits for longer than the debounce or the minimum interval, whichever is longer, so constant output can't hold it back forever.
+    async fn run(
+        self,
+        instance: InstanceName,
+        input: flume::Receiver<ModuleOutput>,
+        output: flume::Sender<ModuleOutput>,
+    ) {
+        let min_interval = Duration::from_millis(self.min_interval_ms);
+        let debounce = Duration::from_millis(self.debounce_ms);
+        let max_wait = debounce.max(min_interval);
+
+        let mut pending = Pending::default();
+        let mut last_received = tokio::time::Instant::now();
+        let mut first_pending = last_received;
+        let mut last_flush: Option<tokio::time::Instant> = None;
+
+        loop {
+            let quiet = last_received + debounce;
+            let deadline = match last_flush {
+                Some(flushed) => quiet.max(flushed + min_interval),
+                None => quiet,
+            };
+            // This is never before the minimum interval, since the first pending output came after the last flush.
+            let deadline = deadline.min(first_pending + max_wait);
+
+            select! {
+                received = input.recv_async() => match received {
+                    Ok(o) => {
+                        last_received = tokio::time::Instant::now();
+                        if pending.is_empty() {
+                            first_pending = last_received;
+                        }
+                        pending.push(o);
+                    }
+                    Err(_) => break,
+                },
+                () = tokio::time::sleep_until(deadline), if !pending.is_empty() => {
+                    trace!("Flushing {} throttled updates from module {instance}", pending.len());
+                    if !pending.flush(&output).await {
+                        return;
+                    }
+                    last_flush = Some(tokio::time::Instant::now());
+                }
+            }
+        }
+
+        // The module stopped, so there is nothing to wait for anymore.
+        pending.flush(&output).await;
+    }
+}
+
+/// What a piece of output replaces when it is coalesced
+#[derive(Debug, PartialEq, Eq)]
+enum OutputKey {
+    Data(Option<ModuleId>, &'static str),
+    Error(Option<ModuleId>),
+}
+impl From<&ModuleOutput> for OutputKey {
+    fn from(output: &ModuleOutput) -> Self {
+        match output {
+            ModuleOutput::Data(data) => {
+                Self::Data(data.specific_target.clone(), data.content.field())
+            }
+            ModuleOutput::Error { widget, .. } => Self::Error(widget.clone()),
+        }
+    }
+}
+
+/// The output that is waiting for the next update
+#[derive(Debug, Default)]
+struct Pending(Vec<(OutputKey, ModuleOutput)>);
+impl Pending {
+    /// Add output, replacing any older output of the same field
+    fn push(&mut self, output: ModuleOutput) {
+        let key = OutputKey::from(&output);
+        self.0.retain(|(k, _)| *k != key);
+        self.0.push((key, output));
+    }
+
+    #[inline]
+    fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    #[inline]
+    fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Send all the pending output. This returns false if the receiver was dropped.
+    async fn flush(&mut self, output: &flume::Sender<ModuleOutput>) -> bool {
+        for (_, o) in self.0.drain(..) {
+            if output.send_async(o).await.is_err() {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::modules::{time::TimeData, Data, ModuleData};
+
+    fn time(widget: &ModuleId, text: &str) -> ModuleOutput {
+        ModuleData {
+            specific_target: Some(widget.clone()),
+            content: Data::Time(TimeData(text.to_owned())),
+        }
+        .into()
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn coalesces_within_interval() {
+        let widget = ModuleId::new("test/throttled_time").unwrap();
+        let throttle = ThrottleKnown {
+            min_interval_ms: 1000,
+            debounce_ms: 0,
+        };
+
+        let (input, receiver) = flume::unbounded();
+        let (output, throttled) = flume::unbounded();
+        tokio::spawn(throttle.run("time".into(), receiver, output));
+
+        input.send_async(time(&widget, "1")).await.unwrap();
+        assert_eq!(throttled.recv_async().await.unwrap(), time(&widget, "1"));
+
+        for text in ["2", "3", "4"] {
+            input.send_async(time(&widget, text)).await.unwrap();
+        }
+        tokio::time::sleep(Duration::from_millis(500)).await;
+        assert!(throttled.is_empty());
+
+        assert_eq!(throttled.recv_async().await.unwrap(), time(&widget, "4"));
+        drop(input);
+        assert!(throttled.recv_async().await.is_err());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn constant_output_still_updates() {
+        let widget = ModuleId::new("test/debounced_time").unwrap();
+        let throttle = ThrottleKnown {
+            min_interval_ms: 0,
+            debounce_ms: 100,
+        };
+
+        let (input, receiver) = flume::unbounded();
+        let (output, throttled) = flume::unbounded();
+        tokio::spawn(throttle.run("time".into(), receiver, output));
+
+        // The output never stops for as long as the debounce
+        let sending = tokio::spawn(async move {
+            for n in 0.. {
+                input
+                    .send_async(time(&widget, &n.to_string()))
+                    .await
+                    .unwrap();
+                tokio::time::sleep(Duration::from_millis(50)).await;
+            }
+        });
+
+        let started = tokio::time::Instant::now();
+        throttled.recv_async().await.unwrap();
+        assert!(started.elapsed() <= Duration::from_millis(100));
+
+        sending.abort();
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn constant_output_with_longer_debounce_than_interval() {
+        let widget = ModuleId::new("test/busy_time").unwrap();
+        let throttle = ThrottleKnown {
+            min_interval_ms: 200,
+            debounce_ms: 500,
+        };
+
+        let (input, receiver) = flume::unbounded();
+        let (output, throttled) = flume::unbounded();
+        tokio::spawn(throttle.run("time".into(), receiver, output));
+
+        let sending = tokio::spawn(async move {
+            for n in 0.. {
+                input
+                    .send_async(time(&widget, &n.to_string()))
+                    .await
+                    .unwrap();
+                tokio::time::sleep(Duration::from_millis(50)).await;
+            }
+        });
+
+        // It updates about every debounce, never waiting on the quiet period that never comes
+        let mut last = tokio::time::Instant::now();
+        for _ in 0..3 {
+            throttled.recv_async().await.unwrap();
+            let waited = last.elapsed();
+            assert!(
+                waited >= Duration::from_millis(200),
+                "Updated after {waited:?}"
+            );
+            assert!(
+                waited <= Duration::from_millis(550),
+                "Updated after {waited:?}"
+            );
+            last = tokio::time::Instant::now();
+        }
+
+        sending.abort();
+    }
+}
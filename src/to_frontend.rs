@@ -1,56 +1,912 @@
 //! Various functions that are an abstraction over what the frontend will eventually be like.
 
-use crate::modules::ModuleData;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::introspect::Stats;
+use crate::modules::{
+    Data, ModuleData, ModuleMessage, ModuleOutput, ModuleType, ModuleYield, RequestField,
+};
 use crate::prelude::*;
+use crate::recorder::Recorder;
+use halobar_core::client::{DataRequest, ProviderError, Request};
 
 pub type FrontendSender<T> = Arc<mpsc::UnboundedSender<T>>;
 
+/// The format that widgets use to show errors, unless they set their own.
+pub const DEFAULT_ERROR_FORMAT: &str = "{instance}: {error}";
+
+/// The frontend's side of the [`FrontendMux`]. Get one from a [`FrontendAttacher`].
+#[derive(Debug)]
+pub struct FrontendChannel {
+    /// Send events from a widget to the module that provides its data
+    pub event_sender: FrontendSender<EventData>,
+    /// Receive updates for each widget
+    pub data_receiver: mpsc::UnboundedReceiver<WidgetUpdate>,
+}
+
+/// A widget on the bar, as the frontend sees it.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct WidgetLayout {
+    #[serde(deserialize_with = "ModuleId::deserialize_declared")]
+    pub id: ModuleId,
+    /// The module instance that provides data for this widget
+    pub instance: InstanceName,
+    pub module_type: ModuleType,
+    /// The format string from the config
+    pub format: String,
+    /// The format string that the widget uses while it is errored.
+    ///
+    /// It has the variables `error`, `kind`, `instance`, and `text`, which is the text from before the error.
+    #[serde(default = "default_error_format")]
+    pub error_format: String,
+    /// When the widget is shown
+    #[serde(default)]
+    pub visibility: Visibility,
+}
+
+fn default_error_format() -> String {
+    DEFAULT_ERROR_FORMAT.to_owned()
+}
+
+/// A rule that checks the latest value of one of a widget's fields.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct VisibilityRule {
+    /// The field to check. This can be any variable that the module provides, even if the format does not use it.
+    pub field: String,
+    /// The values that match, as they are shown in the format. If there are none, the rule matches while the field is truthy.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub is: Vec<String>,
+}
+impl VisibilityRule {
+    /// Check if the rule matches the field's value. A field that was never received does not match.
+    pub fn matches(&self, value: Option<&Data>) -> bool {
+        let Some(value) = value else {
+            return false;
+        };
+
+        if self.is.is_empty() {
+            return value.is_truthy();
+        }
+
+        let shown = value.to_string();
+        self.is.iter().any(|v| v.eq_ignore_ascii_case(&shown))
+    }
+}
+
+/// The rules that decide if a widget is shown, based on its data.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Visibility {
+    /// The widget is only shown while all of these rules match
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub show_if: Vec<VisibilityRule>,
+    /// The widget is hidden while any of these rules match
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub hide_if: Vec<VisibilityRule>,
+}
+impl Visibility {
+    /// Get all the rules
+    pub fn rules(&self) -> impl Iterator<Item = &VisibilityRule> {
+        self.show_if.iter().chain(self.hide_if.iter())
+    }
+
+    /// Check if any rule needs this field
+    pub fn uses(&self, field: &str) -> bool {
+        self.rules().any(|r| r.field == field)
+    }
+
+    /// Check if the widget is shown, given a way to look up the latest value of each field.
+    pub fn is_visible<'d>(&self, value: impl Fn(&str) -> Option<&'d Data>) -> bool {
+        self.show_if.iter().all(|r| r.matches(value(&r.field)))
+            && !self.hide_if.iter().any(|r| r.matches(value(&r.field)))
+    }
+}
+
+/// An update for the widgets, sent to the frontend.
+#[derive(Debug, Clone, PartialEq)]
+pub enum WidgetUpdate {
+    /// The widgets on the bar, in order. This is sent at startup and whenever the config is reloaded,
+    /// before any data for the new widgets.
+    Layout(Vec<WidgetLayout>),
+    /// New data for the widget. This always has a `specific_target`, which is the widget it belongs to.
+    Data(ModuleData),
+    /// The module that provides data for this widget failed to get it, or it is down.
+    /// The widget should show that it is errored until it receives new data.
+    Errored {
+        widget: ModuleId,
+        error: ProviderError,
+    },
+}
+
+/// Updates from the runtime about the state of a module
+#[derive(Debug)]
+pub enum ModuleStatus {
+    /// The module instance (re)started and yielded its data.
+    Yielded(InstanceName, ModuleYield),
+    /// The module instance stopped with an error.
+    Stopped {
+        instance: InstanceName,
+        widgets: Vec<ModuleId>,
+        error: ProviderError,
+    },
+    /// The module instance stopped, and its supervisor is going to restart it.
+    Restarting(InstanceName),
+    /// The module instance was stopped because no widgets request its data anymore.
+    Removed(InstanceName),
+    /// The widgets on the bar changed.
+    Layout(Vec<WidgetLayout>),
+    /// A widget started requesting more fields from a module instance that is already running.
+    Subscribe {
+        instance: InstanceName,
+        request: DataRequest,
+    },
+    /// A widget stopped requesting some fields from a module instance that is still running.
+    Unsubscribe {
+        instance: InstanceName,
+        widget: ModuleId,
+        fields: Vec<RequestField>,
+    },
+}
+
+/// Attaches frontends to a [`FrontendMux`], even while it is running.
+#[derive(Debug, Clone)]
+pub struct FrontendAttacher {
+    attach_sender: FrontendSender<mpsc::UnboundedSender<WidgetUpdate>>,
+    event_sender: FrontendSender<EventData>,
+}
+impl FrontendAttacher {
+    /// Attach a new frontend.
+    ///
+    /// It first receives a snapshot of the current layout and the latest data for every widget,
+    /// so it does not have to wait for each value to change. Then it receives every update like the others.
+    pub fn attach(&self) -> R<FrontendChannel> {
+        let (data_sender, data_receiver) = mpsc::unbounded_channel();
+
+        self.attach_sender
+            .send(data_sender)
+            .map_err(|_| eyre!("Failed to attach frontend, the mux is not running"))?;
+
+        Ok(FrontendChannel {
+            event_sender: Arc::clone(&self.event_sender),
+            data_receiver,
+        })
+    }
+}
+
+/// The latest state of every widget, so frontends that attach late can catch up.
+#[derive(Debug, Default)]
+struct Snapshot {
+    layout: Vec<WidgetLayout>,
+    /// The latest data for each field of each widget
+    data: AHashMap<ModuleId, Vec<Data>>,
+    /// The widgets that are errored, and why
+    errors: AHashMap<ModuleId, ProviderError>,
+}
+impl Snapshot {
+    /// Keep track of an update that is being sent to the frontends.
+    fn update(&mut self, update: &WidgetUpdate) {
+        match update {
+            WidgetUpdate::Layout(layout) => {
+                self.layout = layout.clone();
+
+                let is_shown = |widget: &ModuleId| layout.iter().any(|w| w.id == *widget);
+                self.data.retain(|widget, _| is_shown(widget));
+                self.errors.retain(|widget, _| is_shown(widget));
+            }
+            WidgetUpdate::Data(ModuleData {
+                specific_target: Some(widget),
+                content,
+            }) => {
+                self.errors.remove(widget);
+
+                let fields = self.data.entry(widget.clone()).or_default();
+                match fields.iter_mut().find(|d| d.field() == content.field()) {
+                    Some(old) => *old = content.clone(),
+                    None => fields.push(content.clone()),
+                }
+            }
+            WidgetUpdate::Data(_) => {}
+            WidgetUpdate::Errored { widget, error } => {
+                self.errors.insert(widget.clone(), error.clone());
+            }
+        }
+    }
+
+    /// Get the updates that bring a new frontend up to date, in the order the widgets are on the bar.
+    fn updates(&self) -> Vec<WidgetUpdate> {
+        let mut updates = vec![WidgetUpdate::Layout(self.layout.clone())];
+
+        for widget in self.layout.iter() {
+            if let Some(fields) = self.data.get(&widget.id) {
+                updates.extend(fields.iter().map(|content| {
+                    WidgetUpdate::Data(ModuleData {
+                        specific_target: Some(widget.id.clone()),
+                        content: content.clone(),
+                    })
+                }));
+            }
+
+            if let Some(error) = self.errors.get(&widget.id) {
+                updates.push(WidgetUpdate::Errored {
+                    widget: widget.id.clone(),
+                    error: error.clone(),
+                });
+            }
+        }
+
+        updates
+    }
+}
+
+/// A module subscription, along with the widgets that it provides data for.
+#[derive(Debug)]
+struct Subscription {
+    instance: InstanceName,
+    channel: BiChannel<ModuleMessage, ModuleOutput>,
+    /// Messages wait here until the module's channel has room, so they reach the module in the order they were sent.
+    queue: mpsc::UnboundedSender<ModuleMessage>,
+    /// How many events are waiting in the queue. Past the capacity of the module's channel, new events are dropped.
+    queued_events: Arc<AtomicUsize>,
+    widgets: Vec<ModuleId>,
+}
+impl Subscription {
+    fn new(
+        instance: InstanceName,
+        channel: BiChannel<ModuleMessage, ModuleOutput>,
+        widgets: Vec<ModuleId>,
+    ) -> Self {
+        let (queue, mut queued) = mpsc::unbounded_channel();
+        let queued_events = Arc::new(AtomicUsize::new(0));
+
+        // This ends when the subscription is replaced or removed, or when the module drops its channel.
+        let sender = Arc::clone(&channel.sender);
+        let events = Arc::clone(&queued_events);
+        tokio::spawn(async move {
+            while let Some(message) = queued.recv().await {
+                let is_event = matches!(message, ModuleMessage::Event { .. });
+                let result = sender.send_async(message).await;
+
+                if is_event {
+                    events.fetch_sub(1, Ordering::Relaxed);
+                }
+                if result.is_err() {
+                    break;
+                }
+            }
+        });
+
+        Self {
+            instance,
+            channel,
+            queue,
+            queued_events,
+            widgets,
+        }
+    }
+}
+
 /// A sender/receiver that makes sure messages go to the proper places.
 pub struct FrontendMux {
-    pub message_sender: FrontendSender<ModuleData>,
-    message_receiver: mpsc::UnboundedReceiver<ModuleData>,
-    event_sender: FrontendSender<Event>,
-    event_receiver: mpsc::UnboundedReceiver<Event>,
+    pub message_sender: FrontendSender<ModuleOutput>,
+    message_receiver: mpsc::UnboundedReceiver<ModuleOutput>,
+    event_sender: FrontendSender<EventData>,
+    event_receiver: mpsc::UnboundedReceiver<EventData>,
+    status_sender: FrontendSender<ModuleStatus>,
+    status_receiver: mpsc::UnboundedReceiver<ModuleStatus>,
+    attach_sender: FrontendSender<mpsc::UnboundedSender<WidgetUpdate>>,
+    attach_receiver: mpsc::UnboundedReceiver<mpsc::UnboundedSender<WidgetUpdate>>,
+    router: Router,
 }
 impl FrontendMux {
-    /// Create a new `FrontendMux`.
+    /// Create a new `FrontendMux`. Frontends talk to it through a [`FrontendAttacher`].
     /// This must be called once, upon initialization at startup.
     pub fn new() -> Self {
         let (message_sender, message_receiver) = mpsc::unbounded_channel();
         let (event_sender, event_receiver) = mpsc::unbounded_channel();
+        let (status_sender, status_receiver) = mpsc::unbounded_channel();
+        let (attach_sender, attach_receiver) = mpsc::unbounded_channel();
 
         Self {
             message_sender: Arc::new(message_sender),
             message_receiver,
             event_sender: Arc::new(event_sender),
             event_receiver,
+            status_sender: Arc::new(status_sender),
+            status_receiver,
+            attach_sender: Arc::new(attach_sender),
+            attach_receiver,
+            router: Router {
+                frontends: RefCell::new(Vec::new()),
+                snapshot: RefCell::new(Snapshot::default()),
+                subscriptions: Vec::new(),
+                owners: AHashMap::new(),
+                recorder: None,
+                stats: Stats::default(),
+            },
         }
     }
 
-    /// Run this muxer. This should not return.
-    ///
-    /// It only has a result type so it picks up eyre info if anything goes wrong.
+    /// Get a new attacher for frontends. Frontends attached before [`FrontendMux::run`] are attached when it starts.
+    #[inline]
+    pub fn attacher(&self) -> FrontendAttacher {
+        FrontendAttacher {
+            attach_sender: Arc::clone(&self.attach_sender),
+            event_sender: Arc::clone(&self.event_sender),
+        }
+    }
+
+    /// Get a new sender for widget events
+    #[inline]
+    pub fn event_sender(&self) -> FrontendSender<EventData> {
+        Arc::clone(&self.event_sender)
+    }
+
+    /// Get a new sender for module status updates. This is meant for the runtime.
+    #[inline]
+    pub fn status_sender(&self) -> FrontendSender<ModuleStatus> {
+        Arc::clone(&self.status_sender)
+    }
+
+    /// Get the statistics of the modules, which the mux keeps up to date.
+    #[inline]
+    pub fn stats(&self) -> Stats {
+        self.router.stats.clone()
+    }
+
+    /// Record everything that is sent to the frontend from now on.
+    #[inline]
+    pub fn record_to(&mut self, recorder: Recorder) {
+        self.router.recorder = Some(recorder);
+    }
+
+    /// Tell the frontend which widgets are on the bar. This must be called before [`FrontendMux::add_module`].
+    #[inline]
+    pub fn set_layout(&self, layout: Vec<WidgetLayout>) {
+        self.router.send_frontend(WidgetUpdate::Layout(layout));
+    }
+
+    /// Add a module's yielded data to this mux. This must be called before [`FrontendMux::run`].
     ///
-    /// TODO: Add event handler
+    /// The initial data from the fulfilled requests is sent to the frontend right away.
+    /// Static modules do not yield a subscription, so only their initial data is used.
+    #[inline]
+    pub fn add_module(&mut self, instance: InstanceName, yielded: ModuleYield) {
+        self.router.add_module(instance, yielded);
+    }
+
+    /// Run this muxer. This should not return.
     pub async fn run(self) -> ! {
-        let mut event_receiver = self.event_receiver;
-        let mut message_receiver = self.message_receiver;
+        // The senders are kept alive here so that the receivers never close.
+        let Self {
+            message_sender: _message_sender,
+            mut message_receiver,
+            event_sender: _event_sender,
+            mut event_receiver,
+            status_sender: _status_sender,
+            mut status_receiver,
+            attach_sender: _attach_sender,
+            mut attach_receiver,
+            mut router,
+        } = self;
+
+        let mut subscription_data = futures_util::stream::select_all(
+            (0..router.subscriptions.len()).map(|index| router.subscription_stream(index)),
+        );
 
-        // TODO: Add event handler
         loop {
             select! {
                 maybe_event = event_receiver.recv() => {
                     let event = maybe_event.expect("halobar event receiver closed unexpectedly! Please file a bug report!");
 
-                    debug!("Received event {event:?}");
+                    trace!("Received event {event:?}");
+                    router.route_event(event);
                 }
 
                 maybe_message = message_receiver.recv() => {
                     let message = maybe_message.expect("halobar message receiver closed unexpectedly! Please file a bug report!");
 
-                    debug!("Received message: {message:?}");
+                    trace!("Received message: {message:?}");
+                    match message {
+                        ModuleOutput::Data(data) if data.specific_target.is_some() => {
+                            router.send_frontend(WidgetUpdate::Data(data))
+                        }
+                        ModuleOutput::Error { widget: Some(widget), error } => {
+                            router.send_frontend(WidgetUpdate::Errored { widget, error })
+                        }
+                        message => warn!("Received a message without a target: {message:?}"),
+                    }
+                }
+
+                maybe_status = status_receiver.recv() => {
+                    let status = maybe_status.expect("halobar status receiver closed unexpectedly! Please file a bug report!");
+
+                    match status {
+                        ModuleStatus::Yielded(instance, yielded) => {
+                            // The old subscription stream ends by itself when the old module drops its channel.
+                            if let Some(index) = router.add_module(instance, yielded) {
+                                subscription_data.push(router.subscription_stream(index));
+                            }
+                        }
+                        ModuleStatus::Stopped { instance, widgets, error } => {
+                            warn!("Module {instance} stopped: {}", error.report());
+                            router.stats.stopped(&instance, &error);
+                            for widget in widgets {
+                                router.send_frontend(WidgetUpdate::Errored { widget, error: error.clone() });
+                            }
+                        }
+                        ModuleStatus::Restarting(instance) => router.stats.restarting(&instance),
+                        ModuleStatus::Removed(instance) => router.remove_module(instance),
+                        ModuleStatus::Layout(layout) => router.set_layout(layout),
+                        ModuleStatus::Subscribe { instance, request } => router.subscribe(instance, request),
+                        ModuleStatus::Unsubscribe { instance, widget, fields } => {
+                            router.unsubscribe(instance, widget, fields)
+                        }
+                    }
+                }
+
+                maybe_frontend = attach_receiver.recv() => {
+                    let frontend = maybe_frontend.expect("halobar frontend attach receiver closed unexpectedly! Please file a bug report!");
+                    router.attach(frontend);
+                }
+
+                Some((index, output)) = subscription_data.next() => {
+                    router.stats.received(&router.subscriptions[index].instance, &output);
+                    match output {
+                        ModuleOutput::Data(data) => router.route_data(index, data),
+                        ModuleOutput::Error { widget, error } => router.route_error(index, widget, error),
+                    }
                 }
             }
         }
     }
 }
+
+impl Default for FrontendMux {
+    #[inline]
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The part of the mux that knows where everything goes.
+struct Router {
+    /// Every frontend that is attached
+    frontends: RefCell<Vec<mpsc::UnboundedSender<WidgetUpdate>>>,
+    /// The state that is sent to frontends when they attach
+    snapshot: RefCell<Snapshot>,
+    /// The subscriptions that each module yielded
+    subscriptions: Vec<Subscription>,
+    /// The index of the subscription that owns each widget
+    owners: AHashMap<ModuleId, usize>,
+    recorder: Option<Recorder>,
+    stats: Stats,
+}
+impl Router {
+    /// Add a module's yielded data, sending its initial data to the frontend.
+    ///
+    /// If the module already had a subscription, it is replaced.
+    /// This returns the index of the subscription, if the module yielded one.
+    fn add_module(&mut self, instance: InstanceName, yielded: ModuleYield) -> Option<usize> {
+        let mut widgets = Vec::with_capacity(yielded.fulfilled_requests.len());
+
+        for request in yielded.fulfilled_requests {
+            for field in request.data_fields {
+                match field {
+                    Request::Fulfilled(mut data) => {
+                        data.specific_target = Some(request.id.clone());
+                        self.send_frontend(WidgetUpdate::Data(data));
+                    }
+                    Request::Error(error) => {
+                        warn!(
+                            "Module {instance} could not fulfill request for widget {}: {}",
+                            request.id,
+                            error.report()
+                        );
+                        self.send_frontend(WidgetUpdate::Errored {
+                            widget: request.id.clone(),
+                            error,
+                        });
+                    }
+                    Request::Request(r) => {
+                        warn!(
+                            "Module {instance} ignored request for widget {}: {r:?}",
+                            request.id
+                        )
+                    }
+                }
+            }
+
+            widgets.push(request.id);
+        }
+
+        self.stats.yielded(&instance, &widgets);
+
+        let Some(channel) = yielded.subscription else {
+            debug!("Module {instance} is static");
+            return None;
+        };
+
+        let existing = self.subscription_index(&instance);
+        let index = existing.unwrap_or(self.subscriptions.len());
+
+        // The widgets might have changed since the last time this module yielded.
+        if let Some(i) = existing {
+            self.disown(i);
+        }
+
+        for widget in widgets.iter() {
+            if let Some(previous) = self.owners.insert(widget.clone(), index) {
+                if previous != index {
+                    warn!(
+                        "Widget {widget} was already owned by subscription {previous}, overwriting"
+                    );
+                }
+            }
+        }
+
+        let subscription = Subscription::new(instance, channel, widgets);
+
+        match existing {
+            Some(i) => {
+                debug!(
+                    "Replacing subscription for module {}",
+                    subscription.instance
+                );
+                self.subscriptions[i] = subscription;
+            }
+            None => self.subscriptions.push(subscription),
+        }
+
+        Some(index)
+    }
+
+    /// Forget about a module's widgets, so it does not receive their events anymore.
+    ///
+    /// The subscription itself stays, so that the indices of the other subscriptions do not change.
+    /// Its stream ends when the module drops its channel.
+    fn remove_module(&mut self, instance: InstanceName) {
+        let Some(index) = self.subscription_index(&instance) else {
+            return;
+        };
+
+        debug!("Removing subscription for module {instance}");
+        self.disown(index);
+        self.subscriptions[index].widgets.clear();
+    }
+
+    /// Send the new layout to the frontend, and forget about the widgets that are not on the bar anymore.
+    fn set_layout(&mut self, layout: Vec<WidgetLayout>) {
+        let is_shown = |widget: &ModuleId| layout.iter().any(|w| w.id == *widget);
+
+        self.owners.retain(|widget, _| is_shown(widget));
+        for subscription in self.subscriptions.iter_mut() {
+            subscription.widgets.retain(is_shown);
+            self.stats
+                .set_widgets(&subscription.instance, &subscription.widgets);
+        }
+
+        self.send_frontend(WidgetUpdate::Layout(layout));
+    }
+
+    /// Give a widget more fields from a running module. The widget might be new to the module.
+    fn subscribe(&mut self, instance: InstanceName, request: DataRequest) {
+        let Some(index) = self.subscription_index(&instance) else {
+            warn!(
+                "Module {instance} has no subscription, widget {} can not subscribe to it",
+                request.id
+            );
+            return;
+        };
+
+        debug!(
+            "Widget {} subscribes to more fields from module {instance}",
+            request.id
+        );
+
+        self.owners.insert(request.id.clone(), index);
+        let widgets = &mut self.subscriptions[index].widgets;
+        if !widgets.contains(&request.id) {
+            widgets.push(request.id.clone());
+            self.stats.set_widgets(&instance, widgets);
+        }
+
+        self.send_module(index, ModuleMessage::Subscribe(request));
+    }
+
+    /// Tell a running module that a widget does not need these fields anymore.
+    fn unsubscribe(&self, instance: InstanceName, widget: ModuleId, fields: Vec<RequestField>) {
+        let Some(index) = self.subscription_index(&instance) else {
+            return;
+        };
+
+        debug!("Widget {widget} unsubscribes from some fields of module {instance}");
+        self.send_module(index, ModuleMessage::Unsubscribe { widget, fields });
+    }
+
+    fn subscription_index(&self, instance: &InstanceName) -> Option<usize> {
+        self.subscriptions
+            .iter()
+            .position(|s| s.instance == *instance)
+    }
+
+    /// Remove the ownership of all the widgets owned by the subscription at this index.
+    fn disown(&mut self, index: usize) {
+        self.owners.retain(|_, owner| *owner != index);
+    }
+
+    /// Get a stream of the data from the subscription at this index, tagged with the index.
+    fn subscription_stream(
+        &self,
+        index: usize,
+    ) -> impl futures_util::Stream<Item = (usize, ModuleOutput)> {
+        self.subscriptions[index]
+            .channel
+            .receiver
+            .clone()
+            .into_stream()
+            .map(move |data| (index, data))
+    }
+
+    /// Send an update to every frontend. Data must have a specific target by this point.
+    fn send_frontend(&self, update: WidgetUpdate) {
+        if let Some(ref recorder) = self.recorder {
+            recorder.record(&update);
+        }
+
+        self.snapshot.borrow_mut().update(&update);
+
+        self.frontends.borrow_mut().retain(|frontend| {
+            let attached = frontend.send(update.clone()).is_ok();
+            if !attached {
+                debug!("A frontend detached");
+            }
+            attached
+        });
+    }
+
+    /// Attach a new frontend, sending it the snapshot so it starts with the current state.
+    fn attach(&self, frontend: mpsc::UnboundedSender<WidgetUpdate>) {
+        debug!("Attaching a frontend");
+
+        for update in self.snapshot.borrow().updates() {
+            if frontend.send(update).is_err() {
+                debug!("Frontend detached while it was attaching");
+                return;
+            }
+        }
+
+        self.frontends.borrow_mut().push(frontend);
+    }
+
+    /// Route data that came from a module subscription to the widgets it is meant for.
+    ///
+    /// If the data has no specific target, it is sent to every widget that the subscription provides data for.
+    /// Data for a widget that the subscription does not own is dropped.
+    fn route_data(&self, index: usize, data: ModuleData) {
+        let subscription = &self.subscriptions[index];
+
+        match data.specific_target {
+            Some(ref target) => {
+                if self.owners.get(target) != Some(&index) {
+                    warn!(
+                        "Module {} sent data to widget {target}, which it does not own, dropping it",
+                        subscription.instance
+                    );
+                    return;
+                }
+                self.send_frontend(WidgetUpdate::Data(data));
+            }
+            None => {
+                for widget in subscription.widgets.iter() {
+                    let mut targeted = data.clone();
+                    targeted.specific_target = Some(widget.clone());
+                    self.send_frontend(WidgetUpdate::Data(targeted));
+                }
+            }
+        }
+    }
+
+    /// Send an error from a module subscription to the widget it is meant for,
+    /// or every widget that the subscription provides data for.
+    fn route_error(&self, index: usize, widget: Option<ModuleId>, error: ProviderError) {
+        let subscription = &self.subscriptions[index];
+        debug!(
+            "Module {} sent error: {}",
+            subscription.instance,
+            error.report()
+        );
+
+        let widgets = match widget {
+            Some(ref w) if self.owners.get(w) != Some(&index) => {
+                warn!(
+                    "Module {} sent an error to widget {w}, which it does not own, dropping it",
+                    subscription.instance
+                );
+                return;
+            }
+            Some(ref w) => std::slice::from_ref(w),
+            None => subscription.widgets.as_slice(),
+        };
+
+        for widget in widgets {
+            self.send_frontend(WidgetUpdate::Errored {
+                widget: widget.clone(),
+                error: error.clone(),
+            });
+        }
+    }
+
+    /// Route an event from a widget to the module that owns it.
+    fn route_event(&self, event_data: EventData) {
+        let Some(index) = self.owners.get(&event_data.module) else {
+            debug!(
+                "Widget {} has no module subscription, ignoring event {:?}",
+                event_data.module, event_data.event
+            );
+            return;
+        };
+
+        self.send_module(
+            *index,
+            ModuleMessage::Event {
+                widget: event_data.module,
+                event: event_data.event,
+            },
+        );
+    }
+
+    /// Send a message to the module with the subscription at this index.
+    ///
+    /// Everything goes through the same queue, so an event never overtakes the subscription of its widget.
+    /// Events are dropped if the module is too busy to take them, but subscribe and unsubscribe messages are always queued.
+    fn send_module(&self, index: usize, message: ModuleMessage) {
+        let subscription = &self.subscriptions[index];
+
+        if matches!(message, ModuleMessage::Event { .. }) {
+            let limit = subscription.channel.sender.capacity().unwrap_or(1);
+            if subscription.queued_events.fetch_add(1, Ordering::Relaxed) >= limit {
+                subscription.queued_events.fetch_sub(1, Ordering::Relaxed);
+                warn!(
+                    "Dropping event for module {}, it has too many events queued",
+                    subscription.instance
+                );
+                return;
+            }
+        }
+
+        // This must not block, because the module might be waiting on us to receive its data.
+        if let Err(e) = subscription.queue.send(message) {
+            warn!(
+                "Failed to send message to module {}: {e}",
+                subscription.instance
+            );
+        }
+    }
+}
+
+/// A stand-in for a real frontend. It logs all the data it receives at info level.
+///
+/// It should not return.
+pub async fn eavesdrop(mut frontend: FrontendChannel) {
+    while let Some(data) = frontend.data_receiver.recv().await {
+        info!("{data:?}");
+    }
+
+    warn!("Eavesdrop error: Frontend data sender was dropped!");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::modules::upower::{types::Percentage, UpowerData};
+
+    #[test]
+    fn snapshot_keeps_latest_field() {
+        let widget = ModuleId::new("test/upower").unwrap();
+        let layout = vec![WidgetLayout {
+            id: widget.clone(),
+            instance: "upower".into(),
+            module_type: ModuleType::Upower,
+            format: "{percentage}% {energy}".to_owned(),
+            error_format: DEFAULT_ERROR_FORMAT.to_owned(),
+            visibility: Visibility::default(),
+        }];
+
+        let percentage = |p| {
+            WidgetUpdate::Data(ModuleData {
+                specific_target: Some(widget.clone()),
+                content: Data::Upower(UpowerData::Percentage(Percentage::try_new(p).unwrap())),
+            })
+        };
+        let energy = WidgetUpdate::Data(ModuleData {
+            specific_target: Some(widget.clone()),
+            content: Data::Upower(UpowerData::Energy(42.0)),
+        });
+
+        let mut snapshot = Snapshot::default();
+        snapshot.update(&WidgetUpdate::Layout(layout.clone()));
+        snapshot.update(&percentage(50));
+        snapshot.update(&energy);
+        snapshot.update(&percentage(49));
+
+        assert_eq!(
+            snapshot.updates(),
+            [WidgetUpdate::Layout(layout), percentage(49), energy]
+        );
+    }
+
+    #[tokio::test]
+    async fn queues_subscriptions_for_busy_modules() {
+        let mut router = FrontendMux::new().router;
+        let (module, subscription) = BiChannel::<ModuleOutput, ModuleMessage>::new(1);
+        router.add_module(
+            "plugin".into(),
+            ModuleYield {
+                subscription: Some(subscription),
+                fulfilled_requests: Vec::new(),
+            },
+        );
+
+        let widgets =
+            ["test/first", "test/second", "test/third"].map(|w| ModuleId::new(w).unwrap());
+        for widget in widgets.iter() {
+            router.subscribe(
+                "plugin".into(),
+                DataRequest {
+                    id: widget.clone(),
+                    data_fields: Vec::new(),
+                },
+            );
+        }
+
+        for widget in widgets {
+            let ModuleMessage::Subscribe(request) = module.receiver.recv_async().await.unwrap()
+            else {
+                panic!("The module did not receive the subscription of {widget}");
+            };
+            assert_eq!(request.id, widget);
+        }
+    }
+
+    #[tokio::test]
+    async fn events_wait_for_their_subscription() {
+        let mut router = FrontendMux::new().router;
+        let (module, subscription) = BiChannel::<ModuleOutput, ModuleMessage>::new(1);
+        router.add_module(
+            "plugin".into(),
+            ModuleYield {
+                subscription: Some(subscription),
+                fulfilled_requests: Vec::new(),
+            },
+        );
+
+        let widget = ModuleId::new("test/clicked").unwrap();
+        router.subscribe(
+            "plugin".into(),
+            DataRequest {
+                id: widget.clone(),
+                data_fields: Vec::new(),
+            },
+        );
+        // Only as many events as the module's channel holds can wait, the rest are dropped
+        for event in [Event::Click, Event::RightClick] {
+            router.route_event(EventData {
+                event,
+                module: widget.clone(),
+            });
+        }
+        drop(router);
+
+        let ModuleMessage::Subscribe(request) = module.receiver.recv_async().await.unwrap() else {
+            panic!("The event overtook the subscription");
+        };
+        assert_eq!(request.id, widget);
+
+        let ModuleMessage::Event { event, .. } = module.receiver.recv_async().await.unwrap() else {
+            panic!("The module did not receive the event");
+        };
+        assert_eq!(event, Event::Click);
+
+        assert!(module.receiver.recv_async().await.is_err());
+    }
+}
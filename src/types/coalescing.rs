@@ -0,0 +1,225 @@
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+use crate::prelude::{bail, trace, Arc, R};
+use tokio::sync::Notify;
+
+/// Counters for a [`CoalescingSender`], for debugging.
+#[derive(Debug, Default)]
+pub struct ChannelStats {
+    sent: AtomicU64,
+    merged: AtomicU64,
+    dropped: AtomicU64,
+}
+impl ChannelStats {
+    /// The number of values that made it into the channel
+    pub fn sent(&self) -> u64 {
+        self.sent.load(Ordering::Relaxed)
+    }
+    /// The number of values that were replaced by a newer value with the same key before they were sent
+    pub fn merged(&self) -> u64 {
+        self.merged.load(Ordering::Relaxed)
+    }
+    /// The number of values that were thrown away because the receiver was dropped
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+}
+impl std::fmt::Display for ChannelStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} sent, {} merged, {} dropped",
+            self.sent(),
+            self.merged(),
+            self.dropped()
+        )
+    }
+}
+
+#[derive(Debug)]
+struct Shared<K, T> {
+    /// The values that are waiting to be sent, in the order their keys were first added
+    pending: Mutex<Vec<(K, T)>>,
+    notify: Notify,
+    /// Set when the pump stops, so senders know nobody is listening
+    closed: AtomicBool,
+    /// The number of senders that are alive, so the pump knows when to stop
+    senders: AtomicUsize,
+    stats: ChannelStats,
+}
+
+/// A sender where the latest value wins. Only the newest pending value is kept for each key,
+/// so a slow receiver gets the current state instead of a backlog of outdated values.
+///
+/// This sits in front of the sender of a bounded channel, like the one in a [`crate::types::BiChannel`].
+/// The [`CoalescingPump`] has to be polled for anything to be sent.
+#[derive(Debug)]
+pub struct CoalescingSender<K, T> {
+    shared: Arc<Shared<K, T>>,
+}
+impl<K: PartialEq + std::fmt::Debug, T> CoalescingSender<K, T> {
+    /// Create a new coalescing sender that sends into this channel, along with the pump that does the sending.
+    pub fn new(sender: Arc<flume::Sender<T>>) -> (Self, CoalescingPump<K, T>) {
+        let shared = Arc::new(Shared {
+            pending: Mutex::new(Vec::new()),
+            notify: Notify::new(),
+            closed: AtomicBool::new(false),
+            senders: AtomicUsize::new(1),
+            stats: ChannelStats::default(),
+        });
+
+        let pump = CoalescingPump {
+            shared: Arc::clone(&shared),
+            sender,
+        };
+
+        (Self { shared }, pump)
+    }
+
+    /// Queue a value, replacing the pending value with the same key if there is one. This never blocks.
+    ///
+    /// This returns an error if the pump stopped because the receiver was dropped.
+    pub fn send(&self, key: K, value: T) -> R<()> {
+        if self.shared.closed.load(Ordering::Acquire) {
+            self.shared.stats.dropped.fetch_add(1, Ordering::Relaxed);
+            bail!("Coalescing channel receiver was dropped");
+        }
+
+        {
+            let mut pending = self.shared.pending.lock().unwrap();
+
+            match pending.iter_mut().find(|(k, _)| *k == key) {
+                Some(slot) => {
+                    trace!("Merging pending value for {key:?}");
+                    slot.1 = value;
+                    self.shared.stats.merged.fetch_add(1, Ordering::Relaxed);
+                }
+                None => pending.push((key, value)),
+            }
+        }
+
+        self.shared.notify.notify_one();
+        Ok(())
+    }
+
+    /// Get the counters for this channel
+    #[inline]
+    pub fn stats(&self) -> &ChannelStats {
+        &self.shared.stats
+    }
+}
+impl<K, T> Clone for CoalescingSender<K, T> {
+    fn clone(&self) -> Self {
+        self.shared.senders.fetch_add(1, Ordering::Relaxed);
+        Self {
+            shared: Arc::clone(&self.shared),
+        }
+    }
+}
+impl<K, T> Drop for CoalescingSender<K, T> {
+    fn drop(&mut self) {
+        // This has to be counted before the pump wakes up, so it can see if this was the last sender.
+        self.shared.senders.fetch_sub(1, Ordering::AcqRel);
+        self.shared.notify.notify_one();
+    }
+}
+
+/// The half of a [`CoalescingSender`] that moves the pending values into the channel.
+#[derive(Debug)]
+pub struct CoalescingPump<K, T> {
+    shared: Arc<Shared<K, T>>,
+    sender: Arc<flume::Sender<T>>,
+}
+impl<K, T> CoalescingPump<K, T> {
+    /// Send the pending values as the channel has room for them.
+    ///
+    /// This returns when all the senders are dropped, or with an error when the receiver is dropped.
+    pub async fn run(self) -> R<()> {
+        loop {
+            while let Some((_, value)) = self.next_pending() {
+                if self.sender.send_async(value).await.is_err() {
+                    self.shared.closed.store(true, Ordering::Release);
+
+                    let remaining = self.shared.pending.lock().unwrap().drain(..).count();
+                    self.shared
+                        .stats
+                        .dropped
+                        .fetch_add(1 + remaining as u64, Ordering::Relaxed);
+
+                    bail!("Coalescing channel receiver was dropped");
+                }
+
+                self.shared.stats.sent.fetch_add(1, Ordering::Relaxed);
+            }
+
+            // Only the pump is left
+            if self.shared.senders.load(Ordering::Acquire) == 0 {
+                return Ok(());
+            }
+
+            self.shared.notify.notified().await;
+        }
+    }
+
+    fn next_pending(&self) -> Option<(K, T)> {
+        let mut pending = self.shared.pending.lock().unwrap();
+        if pending.is_empty() {
+            return None;
+        }
+        Some(pending.remove(0))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::prelude::{select, Duration};
+
+    #[tokio::test]
+    async fn latest_value_wins() {
+        let (sender, receiver) = flume::bounded(1);
+        let (coalescing, pump) = CoalescingSender::new(Arc::new(sender));
+
+        coalescing.send("energy", 1).unwrap();
+        coalescing.send("rate", 10).unwrap();
+        coalescing.send("energy", 2).unwrap();
+        coalescing.send("energy", 3).unwrap();
+
+        let receiving = async {
+            let mut received = Vec::new();
+            while received.len() < 2 {
+                received.push(receiver.recv_async().await.unwrap());
+            }
+            received
+        };
+
+        let received = select! {
+            _ = pump.run() => panic!("Pump stopped while a sender was alive"),
+            received = receiving => received,
+        };
+
+        // The energy key was first, so it keeps its place with its newest value.
+        assert_eq!(received, [3, 10]);
+        assert_eq!(coalescing.stats().merged(), 2);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn stops_when_senders_drop() {
+        let (sender, receiver) = flume::unbounded();
+        let (coalescing, pump) = CoalescingSender::new(Arc::new(sender));
+        let pump = tokio::spawn(pump.run());
+
+        let cloned = coalescing.clone();
+        coalescing.send("energy", 1).unwrap();
+        drop(coalescing);
+        tokio::spawn(async move { drop(cloned) });
+
+        tokio::time::timeout(Duration::from_secs(1), pump)
+            .await
+            .expect("Pump kept running after every sender was dropped")
+            .unwrap()
+            .unwrap();
+        assert_eq!(receiver.recv_async().await.unwrap(), 1);
+    }
+}
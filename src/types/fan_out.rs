@@ -0,0 +1,125 @@
+use crate::modules::{Data, ModuleData};
+use crate::prelude::ModuleId;
+
+/// Keeps track of the widgets that requested each field, so that providers compute every field once,
+/// no matter how many widgets requested it, and fan the result out to all of them.
+///
+/// The fields are whatever identifies a piece of data in a provider, like a format string or a property.
+#[derive(Debug, Clone)]
+pub struct FanOut<F> {
+    fields: Vec<(F, Vec<ModuleId>)>,
+}
+impl<F> Default for FanOut<F> {
+    fn default() -> Self {
+        Self { fields: Vec::new() }
+    }
+}
+impl<F: PartialEq> FanOut<F> {
+    #[inline]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a widget to the widgets that requested this field.
+    ///
+    /// This returns true if no widget requested this field before, so the provider has to start computing it.
+    pub fn subscribe(&mut self, field: F, widget: ModuleId) -> bool {
+        match self.fields.iter_mut().find(|(f, _)| *f == field) {
+            Some((_, widgets)) => {
+                if !widgets.contains(&widget) {
+                    widgets.push(widget);
+                }
+                false
+            }
+            None => {
+                self.fields.push((field, vec![widget]));
+                true
+            }
+        }
+    }
+
+    /// Remove a widget from the widgets that requested this field.
+    ///
+    /// This returns true if no widget requests this field anymore, so the provider can stop computing it.
+    pub fn unsubscribe(&mut self, field: &F, widget: &ModuleId) -> bool {
+        let Some(index) = self.fields.iter().position(|(f, _)| f == field) else {
+            return false;
+        };
+
+        let widgets = &mut self.fields[index].1;
+        widgets.retain(|w| w != widget);
+
+        if widgets.is_empty() {
+            self.fields.remove(index);
+            return true;
+        }
+        false
+    }
+
+    /// Remove a widget from every field, returning the fields that no widget requests anymore.
+    pub fn remove_widget(&mut self, widget: &ModuleId) -> Vec<F> {
+        let mut removed = Vec::new();
+
+        for (_, widgets) in self.fields.iter_mut() {
+            widgets.retain(|w| w != widget);
+        }
+
+        let mut index = 0;
+        while index < self.fields.len() {
+            if self.fields[index].1.is_empty() {
+                removed.push(self.fields.remove(index).0);
+            } else {
+                index += 1;
+            }
+        }
+
+        removed
+    }
+
+    /// Get the widgets that requested this field
+    pub fn widgets(&self, field: &F) -> &[ModuleId] {
+        self.fields
+            .iter()
+            .find(|(f, _)| f == field)
+            .map(|(_, widgets)| widgets.as_slice())
+            .unwrap_or_default()
+    }
+
+    /// Iterate over every field that was requested, along with the widgets that requested it.
+    pub fn iter(&self) -> impl Iterator<Item = (&F, &[ModuleId])> {
+        self.fields.iter().map(|(f, w)| (f, w.as_slice()))
+    }
+
+    /// Target a copy of this data at every widget that requested this field.
+    pub fn fan_out(&self, field: &F, content: &Data) -> Vec<ModuleData> {
+        self.widgets(field)
+            .iter()
+            .map(|widget| ModuleData {
+                specific_target: Some(widget.clone()),
+                content: content.clone(),
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fields_are_shared() {
+        let first = ModuleId::new("test/time").unwrap();
+        let second = ModuleId::new("test/time/1").unwrap();
+
+        let mut fan_out = FanOut::new();
+        assert!(fan_out.subscribe("%H:%M", first.clone()));
+        assert!(!fan_out.subscribe("%H:%M", second.clone()));
+        assert!(fan_out.subscribe("%S", second.clone()));
+
+        assert_eq!(fan_out.widgets(&"%H:%M"), [first.clone(), second.clone()]);
+
+        assert!(!fan_out.unsubscribe(&"%H:%M", &first));
+        assert_eq!(fan_out.remove_widget(&second), ["%H:%M", "%S"]);
+        assert_eq!(fan_out.iter().count(), 0);
+    }
+}
@@ -1,15 +1,16 @@
-mod bichannel;
+mod coalescing;
 mod config_flags;
 mod event;
+mod fan_out;
 mod format_state;
 mod internal_error;
-mod module_id;
 mod zbus_connection;
 
-pub use bichannel::BiChannel;
+pub use coalescing::{ChannelStats, CoalescingPump, CoalescingSender};
 pub(crate) use config_flags::data_flags;
 pub use event::EventData;
+pub use fan_out::FanOut;
 pub use format_state::FormatState;
+pub use halobar_core::types::{BiChannel, InstanceName, ModuleId, ModuleIdInteger};
 pub use internal_error::{InternalError, InternalResult};
-pub use module_id::{ModuleId, ModuleIdFactory, ModuleIdInteger};
 pub use zbus_connection::{SessionConnection, SystemConnection};